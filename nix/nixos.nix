@@ -38,6 +38,31 @@ in
                 default = false;
               };
               target = lib.mkOption { type = lib.types.str; };
+              deploy = lib.mkOption {
+                type = lib.types.enum [
+                  "symlink"
+                  "copy"
+                  "hardlink"
+                  "template"
+                ];
+                default = "symlink";
+              };
+              mode = lib.mkOption {
+                type = lib.types.nullOr lib.types.str;
+                default = null;
+              };
+              dirMode = lib.mkOption {
+                type = lib.types.nullOr lib.types.str;
+                default = null;
+              };
+              owner = lib.mkOption {
+                type = lib.types.nullOr lib.types.str;
+                default = null;
+              };
+              group = lib.mkOption {
+                type = lib.types.nullOr lib.types.str;
+                default = null;
+              };
             };
             config =
               let
@@ -65,6 +90,21 @@ in
       default = { };
     };
     targetRoot = lib.mkOption { type = lib.types.str; };
+    conflict = lib.mkOption {
+      type = lib.types.enum [
+        "fail"
+        "backup"
+        "overwrite"
+      ];
+      default = "fail";
+    };
+    crossDevice = lib.mkOption {
+      type = lib.types.enum [
+        "fail"
+        "copy"
+      ];
+      default = "fail";
+    };
     metadataPath = lib.mkOption { type = lib.types.str; };
 
     package = lib.mkOption {
@@ -86,21 +126,27 @@ in
               }
               (
                 let
-                  register = "${cfg.executablePath} register";
-                  registerFiles = lib.strings.concatStrings (
-                    lib.mapAttrsToList (n: v: ''
-                      ${register} $out/share/makky/makky.metadata ${
-                        lib.escapeShellArgs [
-                          v.store.path
-                          v.target
-                        ]
-                      }
-                    '') cfg.files
+                  files = pkgs.writeText "makky-files.json" (
+                    builtins.toJSON (
+                      lib.mapAttrsToList (
+                        n: v:
+                        lib.filterAttrs (_: value: value != null) {
+                          source = "${v.store.path}";
+                          target = v.target;
+                          deploy = v.deploy;
+                          mode = v.mode;
+                          dir_mode = v.dirMode;
+                          owner = v.owner;
+                          group = v.group;
+                        }
+                      ) cfg.files
+                    )
                   );
                 in
                 ''
                   mkdir -p $out/share/makky
-                  ${registerFiles}
+                  ${cfg.executablePath} import $out/share/makky/makky.metadata ${files}
+                  ${cfg.executablePath} validate $out/share/makky/makky.metadata
                 ''
               );
         in
@@ -109,7 +155,6 @@ in
           system.userActivationScripts.makkyLink =
             let
               metadataStorePath = "${packageFiles}/share/makky/makky.metadata";
-              diff = "${pkgs.diffutils}/bin/diff";
             in
             ''
               function __makky_activate() {
@@ -117,32 +162,8 @@ in
                 local metadata_store=${metadataStorePath}
                 local target_root=${cfg.targetRoot}
                 local makky_executable=${cfg.executablePath}
-                local diff_executable=${diff}
-                local do_unlink=false
-                local do_link=false
-
-                if [ -f $metadata_actual ]; then
-                  set +e
-                  $diff_executable -q $metadata_actual $metadata_store
-                  diff_status=$?
-                  set -e
-                  if [ $diff_status -ne 0 ]; then
-                    do_unlink=true
-                    do_link=true
-                  fi
-                else
-                  do_link=true
-                fi
-
-                if [ $do_unlink = true ]; then
-                  $makky_executable unlink $metadata_actual $target_root
-                  rm -f $metadata_actual
-                fi
 
-                if [ $do_link = true ]; then
-                  cp $metadata_store $metadata_actual
-                  $makky_executable link $metadata_store $target_root
-                fi
+                $makky_executable switch --conflict=${cfg.conflict} --cross-device=${cfg.crossDevice} $metadata_actual $metadata_store $target_root
               }
 
               __makky_activate
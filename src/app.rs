@@ -30,6 +30,15 @@ impl error::Error for Error {
     }
 }
 
+impl Error {
+    fn is_drifted(&self) -> bool {
+        matches!(
+            self,
+            Self::Handler(handler::Error::DiffDrifted(_) | handler::Error::StatusDrifted(_))
+        )
+    }
+}
+
 impl From<command::Error> for Error {
     fn from(value: command::Error) -> Self {
         Self::Command(value)
@@ -53,7 +62,11 @@ impl Termination for Status {
             Self::Ok => ExitCode::SUCCESS,
             Self::Err(err) => {
                 eprintln!("Error: {err}");
-                ExitCode::FAILURE
+                if err.is_drifted() {
+                    ExitCode::from(3)
+                } else {
+                    ExitCode::FAILURE
+                }
             }
         }
     }
@@ -61,9 +74,17 @@ impl Termination for Status {
 
 fn execute() -> Result<(), Error> {
     match command::parse()? {
+        command::Type::Diff(args) => handler::diff(args)?,
+        command::Type::Import(args) => handler::import(args)?,
         command::Type::Link(args) => handler::link(args)?,
+        command::Type::List(args) => handler::list(args)?,
+        command::Type::Migrate(args) => handler::migrate(args)?,
         command::Type::Register(args) => handler::register(args)?,
+        command::Type::Status(args) => handler::status(args)?,
+        command::Type::Switch(args) => handler::switch(args)?,
         command::Type::Unlink(args) => handler::unlink(args)?,
+        command::Type::Unregister(args) => handler::unregister(args)?,
+        command::Type::Validate(args) => handler::validate(args)?,
     }
     Ok(())
 }
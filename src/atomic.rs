@@ -0,0 +1,39 @@
+use std::{
+    ffi::OsString,
+    fs::{metadata, remove_file, rename, set_permissions, File},
+    io::{self, Write},
+    path::Path,
+};
+
+pub fn lock(path: &Path) -> Result<File, io::Error> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file = File::open(directory)?;
+    file.lock()?;
+    Ok(file)
+}
+
+pub fn write(path: &Path, data: &[u8]) -> Result<(), io::Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_file_name = OsString::from(".");
+    temp_file_name.push(file_name);
+    temp_file_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_file_name);
+    let permissions = metadata(path).ok().map(|metadata| metadata.permissions());
+    let result = File::create(&temp_path).and_then(|mut file| {
+        if let Some(permissions) = permissions {
+            set_permissions(&temp_path, permissions)?;
+        }
+        file.write_all(data)?;
+        file.sync_all()?;
+        rename(&temp_path, path)
+    });
+    if result.is_err() {
+        let _ = remove_file(&temp_path);
+    }
+    result
+}
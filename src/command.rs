@@ -1,104 +1,342 @@
-use std::{error, fmt, path::PathBuf, str::FromStr};
+use std::{error, ffi::OsString, fmt, os::unix::ffi::OsStrExt, path::PathBuf, str::FromStr};
+
+use crate::symlink;
 
 #[derive(Debug)]
 pub enum Type {
+    Diff(ArgsDiff),
+    Import(ArgsImport),
     Link(ArgsLink),
+    List(ArgsList),
+    Migrate(ArgsMigrate),
     Register(ArgsRegister),
+    Status(ArgsStatus),
+    Switch(ArgsSwitch),
     Unlink(ArgsUnlink),
+    Unregister(ArgsUnregister),
+    Validate(ArgsValidate),
+}
+
+#[derive(Debug)]
+pub struct ArgsDiff {
+    pub metadata_path: PathBuf,
+    pub target_root: PathBuf,
+    pub target: Option<OsString>,
+}
+
+#[derive(Debug)]
+pub struct ArgsImport {
+    pub metadata_path: PathBuf,
+    pub json_path: PathBuf,
 }
 
 #[derive(Debug)]
 pub struct ArgsLink {
+    pub conflict: symlink::Conflict,
+    pub cross_device: symlink::CrossDevice,
+    pub dry_run: bool,
     pub metadata_path: PathBuf,
     pub target_root: PathBuf,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    Json,
+    Text,
+}
+
+#[derive(Debug)]
+pub struct ArgsList {
+    pub format: ListFormat,
+    pub metadata_path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct ArgsMigrate {
+    pub metadata_path: PathBuf,
+}
+
 #[derive(Debug)]
 pub struct ArgsRegister {
+    pub deploy: symlink::Deploy,
+    pub dir_mode: Option<String>,
+    pub group: Option<String>,
     pub metadata_path: PathBuf,
-    pub source: String,
-    pub target: String,
+    pub mode: Option<String>,
+    pub owner: Option<String>,
+    pub source: OsString,
+    pub target: OsString,
+}
+
+#[derive(Debug)]
+pub struct ArgsStatus {
+    pub metadata_path: PathBuf,
+    pub target_root: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct ArgsSwitch {
+    pub conflict: symlink::Conflict,
+    pub cross_device: symlink::CrossDevice,
+    pub dry_run: bool,
+    pub metadata_path: PathBuf,
+    pub new_metadata_path: PathBuf,
+    pub target_root: PathBuf,
 }
 
 #[derive(Debug)]
 pub struct ArgsUnlink {
+    pub dry_run: bool,
     pub metadata_path: PathBuf,
     pub target_root: PathBuf,
 }
 
+#[derive(Debug)]
+pub struct ArgsUnregister {
+    pub metadata_path: PathBuf,
+    pub target: OsString,
+    pub unlink: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct ArgsValidate {
+    pub metadata_path: PathBuf,
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Name {
+    Diff,
+    Import,
     Link,
+    List,
+    Migrate,
     Register,
+    Status,
+    Switch,
     Unlink,
+    Unregister,
+    Validate,
 }
 
 impl FromStr for Name {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
+            "diff" => Self::Diff,
+            "import" => Self::Import,
             "link" => Self::Link,
+            "list" => Self::List,
+            "migrate" => Self::Migrate,
             "register" => Self::Register,
+            "status" => Self::Status,
+            "switch" => Self::Switch,
             "unlink" => Self::Unlink,
+            "unregister" => Self::Unregister,
+            "validate" => Self::Validate,
             _ => return Err(Error::UnknownCommand(String::from(s))),
         })
     }
 }
 
+struct Options(Vec<String>);
+
+impl Options {
+    fn flag(&mut self, name: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|option| option != name);
+        self.0.len() != len
+    }
+
+    fn value(&mut self, name: &str) -> Option<String> {
+        let prefix = format!("{name}=");
+        let idx = self.0.iter().position(|option| option.starts_with(&prefix))?;
+        let option = self.0.remove(idx);
+        Some(String::from(&option[prefix.len()..]))
+    }
+
+    fn conflict(&mut self) -> Result<symlink::Conflict, Error> {
+        Ok(match self.value("--conflict").as_deref() {
+            None | Some("fail") => symlink::Conflict::Fail,
+            Some("backup") => symlink::Conflict::Backup,
+            Some("overwrite") => symlink::Conflict::Overwrite,
+            Some(value) => return Err(Error::UnknownConflict(String::from(value))),
+        })
+    }
+
+    fn cross_device(&mut self) -> Result<symlink::CrossDevice, Error> {
+        Ok(match self.value("--cross-device").as_deref() {
+            None | Some("fail") => symlink::CrossDevice::Fail,
+            Some("copy") => symlink::CrossDevice::Copy,
+            Some(value) => return Err(Error::UnknownCrossDevice(String::from(value))),
+        })
+    }
+
+    fn deploy(&mut self) -> Result<symlink::Deploy, Error> {
+        match self.value("--deploy") {
+            None => Ok(symlink::Deploy::default()),
+            Some(value) => value.parse().map_err(Error::UnknownDeploy),
+        }
+    }
+
+    fn list_format(&mut self) -> Result<ListFormat, Error> {
+        Ok(match self.value("--format").as_deref() {
+            None | Some("text") => ListFormat::Text,
+            Some("json") => ListFormat::Json,
+            Some(value) => return Err(Error::UnknownFormat(String::from(value))),
+        })
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self.0.into_iter().next() {
+            Some(option) => Err(Error::UnknownOption(option)),
+            None => Ok(()),
+        }
+    }
+}
+
 pub fn parse() -> Result<Type, Error> {
-    let mut args = std::env::args().skip(1);
+    let (raw_options, raw_args): (Vec<OsString>, Vec<OsString>) = std::env::args_os()
+        .skip(1)
+        .partition(|arg| arg.as_bytes().starts_with(b"--"));
+    let raw_options = raw_options
+        .into_iter()
+        .map(|option| {
+            option
+                .into_string()
+                .map_err(|option| Error::UnknownOption(option.to_string_lossy().into_owned()))
+        })
+        .collect::<Result<Vec<String>, Error>>()?;
+    let mut options = Options(raw_options);
+    let mut args = raw_args.into_iter();
     let raw_name = args.next().ok_or(Error::CommandNotProvided)?;
-    let name = raw_name.parse::<Name>()?;
+    let name = raw_name.to_string_lossy().parse::<Name>()?;
     let raw_metadata_path = args.next().ok_or(Error::MetadataPathNotProvided)?;
     let metadata_path = PathBuf::from(raw_metadata_path);
-    Ok(match name {
+    let result = match name {
+        Name::Diff => {
+            let raw_target_root = args.next().ok_or(Error::TargetRootNotProvided)?;
+            let target_root = PathBuf::from(raw_target_root);
+            Type::Diff(ArgsDiff {
+                metadata_path,
+                target_root,
+                target: args.next(),
+            })
+        }
+        Name::Import => {
+            let json_path = args.next().ok_or(Error::JsonPathNotProvided)?;
+            Type::Import(ArgsImport {
+                metadata_path,
+                json_path: PathBuf::from(json_path),
+            })
+        }
         Name::Link => {
             let raw_target_root = args.next().ok_or(Error::TargetRootNotProvided)?;
             let target_root = PathBuf::from(raw_target_root);
             Type::Link(ArgsLink {
+                conflict: options.conflict()?,
+                cross_device: options.cross_device()?,
+                dry_run: options.flag("--dry-run"),
                 metadata_path,
                 target_root,
             })
         }
+        Name::List => Type::List(ArgsList {
+            format: options.list_format()?,
+            metadata_path,
+        }),
+        Name::Migrate => Type::Migrate(ArgsMigrate { metadata_path }),
         Name::Register => {
             let source = args.next().ok_or(Error::LinkSourceNotProvided)?;
             let target = args.next().ok_or(Error::LinkTargetNotProvided)?;
             Type::Register(ArgsRegister {
+                deploy: options.deploy()?,
+                dir_mode: options.value("--dir-mode"),
+                group: options.value("--group"),
                 metadata_path,
+                mode: options.value("--mode"),
+                owner: options.value("--owner"),
                 source,
                 target,
             })
         }
+        Name::Status => {
+            let raw_target_root = args.next().ok_or(Error::TargetRootNotProvided)?;
+            let target_root = PathBuf::from(raw_target_root);
+            Type::Status(ArgsStatus {
+                metadata_path,
+                target_root,
+            })
+        }
+        Name::Switch => {
+            let raw_new_metadata_path = args.next().ok_or(Error::NewMetadataPathNotProvided)?;
+            let new_metadata_path = PathBuf::from(raw_new_metadata_path);
+            let raw_target_root = args.next().ok_or(Error::TargetRootNotProvided)?;
+            let target_root = PathBuf::from(raw_target_root);
+            Type::Switch(ArgsSwitch {
+                conflict: options.conflict()?,
+                cross_device: options.cross_device()?,
+                dry_run: options.flag("--dry-run"),
+                metadata_path,
+                new_metadata_path,
+                target_root,
+            })
+        }
         Name::Unlink => {
             let raw_target_root = args.next().ok_or(Error::TargetRootNotProvided)?;
             let target_root = PathBuf::from(raw_target_root);
             Type::Unlink(ArgsUnlink {
+                dry_run: options.flag("--dry-run"),
                 metadata_path,
                 target_root,
             })
         }
-    })
+        Name::Unregister => {
+            let target = args.next().ok_or(Error::LinkTargetNotProvided)?;
+            Type::Unregister(ArgsUnregister {
+                metadata_path,
+                target,
+                unlink: options.value("--unlink").map(PathBuf::from),
+            })
+        }
+        Name::Validate => Type::Validate(ArgsValidate { metadata_path }),
+    };
+    options.finish()?;
+    Ok(result)
 }
 
 #[derive(Debug)]
 pub enum Error {
     CommandNotProvided,
+    JsonPathNotProvided,
     LinkSourceNotProvided,
     LinkTargetNotProvided,
     MetadataPathNotProvided,
+    NewMetadataPathNotProvided,
     TargetRootNotProvided,
     UnknownCommand(String),
+    UnknownConflict(String),
+    UnknownCrossDevice(String),
+    UnknownDeploy(String),
+    UnknownFormat(String),
+    UnknownOption(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::CommandNotProvided => write!(out, "command not provided"),
+            Self::JsonPathNotProvided => write!(out, "json path not provided"),
             Self::LinkSourceNotProvided => write!(out, "link source not provided"),
             Self::LinkTargetNotProvided => write!(out, "link target not provided"),
             Self::MetadataPathNotProvided => write!(out, "metadata path not provided"),
+            Self::NewMetadataPathNotProvided => write!(out, "new metadata path not provided"),
             Self::TargetRootNotProvided => write!(out, "target root not provided"),
             Self::UnknownCommand(value) => write!(out, "unknown command: {value}"),
+            Self::UnknownConflict(value) => write!(out, "unknown conflict policy: {value}"),
+            Self::UnknownCrossDevice(value) => write!(out, "unknown cross-device policy: {value}"),
+            Self::UnknownDeploy(value) => write!(out, "unknown deploy mode: {value}"),
+            Self::UnknownFormat(value) => write!(out, "unknown format: {value}"),
+            Self::UnknownOption(value) => write!(out, "unknown option: {value}"),
         }
     }
 }
@@ -0,0 +1,28 @@
+pub fn encode(value: &[u8], out: &mut Vec<u8>) {
+    for byte in value {
+        match byte {
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            _ => out.push(*byte),
+        }
+    }
+}
+
+pub fn decode(value: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut bytes = value.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte == b'\\' {
+            result.push(match bytes.next()? {
+                b'\\' => b'\\',
+                b't' => b'\t',
+                b'n' => b'\n',
+                _ => return None,
+            });
+        } else {
+            result.push(*byte);
+        }
+    }
+    Some(result)
+}
@@ -1,59 +1,367 @@
 use std::{error, fmt, path::PathBuf};
 
-use crate::{command, metadata, symlink};
+use crate::{command, json, manifest, metadata, symlink, template, unified};
+
+pub fn diff(args: command::ArgsDiff) -> Result<(), Error> {
+    let manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::DiffReadManifest)?;
+    let entries =
+        metadata::read_entries(args.metadata_path, args.target_root.clone()).map_err(Error::DiffReadMetadata)?;
+    let selected = args.target.map(|target| args.target_root.join(target));
+    let variables = template::variables();
+    let mut found = false;
+    let mut drifted = 0;
+    for entry in entries {
+        let copies =
+            symlink::drift(&entry.source_path, &entry.target_path, entry.deploy, &manifest).map_err(|err| {
+                Error::DiffCheck {
+                    err,
+                    source: entry.source_path.clone(),
+                    target: entry.target_path.clone(),
+                }
+            })?;
+        for copy in copies {
+            if selected
+                .as_ref()
+                .is_some_and(|selected| !copy.target.starts_with(selected))
+            {
+                continue;
+            }
+            found = true;
+            println!("{}: {} -> {}", copy.drift, copy.source.display(), copy.target.display());
+            if copy.drift == symlink::Drift::Clean {
+                continue;
+            }
+            drifted += 1;
+            if copy.drift == symlink::Drift::Modified {
+                let check = |err| Error::DiffCheck {
+                    err,
+                    source: copy.source.clone(),
+                    target: copy.target.clone(),
+                };
+                let deployed = symlink::read_file(&copy.target).map_err(check)?;
+                let source = copy.expected(&variables).map_err(check)?;
+                print!(
+                    "{}",
+                    unified::diff(
+                        &deployed,
+                        &source,
+                        &copy.target.to_string_lossy(),
+                        &copy.source.to_string_lossy()
+                    )
+                );
+            }
+        }
+    }
+    match selected {
+        Some(selected) if !found => Err(Error::DiffTargetNotCopied(selected)),
+        _ if drifted > 0 => Err(Error::DiffDrifted(drifted)),
+        _ => Ok(()),
+    }
+}
 
 pub fn link(args: command::ArgsLink) -> Result<(), Error> {
+    let mut manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::LinkReadManifest)?;
     let entries = metadata::read_entries(args.metadata_path, args.target_root).map_err(Error::LinkReadMetadata)?;
+    metadata::check_sources(&entries).map_err(Error::LinkReadMetadata)?;
+    metadata::check_overlaps(&entries).map_err(Error::LinkReadMetadata)?;
+    if args.conflict == symlink::Conflict::Fail {
+        metadata::check_targets(&entries, &manifest).map_err(Error::LinkReadMetadata)?;
+    }
+    let mut plan = symlink::Plan::new(args.conflict, args.cross_device, &manifest);
     for entry in entries {
-        println!("Creating symlink: {}", entry);
-        symlink::create(&entry.source_path, &entry.target_path).map_err(|err| Error::LinkCreate {
-            err,
-            source: entry.source_path,
-            target: entry.target_path,
-        })?;
+        plan.create(&entry.source_path, &entry.target_path, entry.deploy, &entry.access)
+            .map_err(|err| Error::LinkCreate {
+                err,
+                source: entry.source_path,
+                target: entry.target_path,
+            })?;
+    }
+    plan.finish();
+    execute(&plan, args.dry_run).map_err(Error::LinkApply)?;
+    record(&plan, &mut manifest, args.dry_run).map_err(Error::LinkWriteManifest)
+}
+
+pub fn import(args: command::ArgsImport) -> Result<(), Error> {
+    metadata::import(args.metadata_path, &args.json_path).map_err(Error::ImportMetadata)
+}
+
+pub fn list(args: command::ArgsList) -> Result<(), Error> {
+    let new_entries = metadata::list(args.metadata_path).map_err(Error::ListReadMetadata)?;
+    match args.format {
+        command::ListFormat::Json => {
+            let items = new_entries
+                .iter()
+                .map(metadata::NewEntry::to_json)
+                .collect::<Result<Vec<json::Value>, metadata::Error>>()
+                .map_err(Error::ListJson)?;
+            println!("{}", json::Value::Array(items));
+        }
+        command::ListFormat::Text => {
+            for new_entry in new_entries {
+                println!("{new_entry}");
+            }
+        }
     }
     Ok(())
 }
 
+pub fn migrate(args: command::ArgsMigrate) -> Result<(), Error> {
+    metadata::migrate(args.metadata_path).map_err(Error::MigrateMetadata)
+}
+
 pub fn register(args: command::ArgsRegister) -> Result<(), Error> {
-    let new_entry = metadata::NewEntry::create(args.source, args.target).map_err(Error::RegisterNewEntryCreate)?;
+    let new_entry = metadata::NewEntry::create(args.source, args.target)
+        .map_err(Error::RegisterNewEntryCreate)?
+        .with_deploy(args.deploy)
+        .with_access(args.mode, args.dir_mode, args.owner, args.group)
+        .map_err(Error::RegisterNewEntryCreate)?;
     metadata::write_entry(args.metadata_path, &new_entry).map_err(Error::RegisterNewEntryWrite)?;
     Ok(())
 }
 
-pub fn unlink(args: command::ArgsUnlink) -> Result<(), Error> {
-    let entries = metadata::read_entries(args.metadata_path, args.target_root).map_err(Error::LinkReadMetadata)?;
+pub fn switch(args: command::ArgsSwitch) -> Result<(), Error> {
+    let mut manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::SwitchReadManifest)?;
+    let old_entries = if args.metadata_path.exists() {
+        metadata::read_entries(args.metadata_path.clone(), args.target_root.clone())
+            .map_err(Error::SwitchReadOldMetadata)?
+    } else {
+        Vec::new()
+    };
+    let new_entries = metadata::read_entries(args.new_metadata_path.clone(), args.target_root)
+        .map_err(Error::SwitchReadNewMetadata)?;
+    metadata::check_sources(&new_entries).map_err(Error::SwitchReadNewMetadata)?;
+    metadata::check_overlaps(&new_entries).map_err(Error::SwitchReadNewMetadata)?;
+    if args.conflict == symlink::Conflict::Fail {
+        metadata::check_targets(&new_entries, &manifest).map_err(Error::SwitchReadNewMetadata)?;
+    }
+    let diff = metadata::EntriesDiff::new(old_entries, new_entries);
+    let mut plan = symlink::Plan::new(args.conflict, args.cross_device, &manifest);
+
+    let removed_entries = diff
+        .removed
+        .iter()
+        .chain(diff.changed.iter().map(|(old_entry, _)| old_entry));
+    for entry in removed_entries {
+        match plan.remove(&entry.source_path, &entry.target_path) {
+            Err(err @ (symlink::Error::TargetForeign(_) | symlink::Error::TargetOccupied(_))) => {
+                eprintln!("Warning: skip {}: {err}", entry.target_path.display());
+            }
+            result => result.map_err(|err| Error::SwitchRemove {
+                err,
+                source: entry.source_path.clone(),
+                target: entry.target_path.clone(),
+            })?,
+        }
+    }
+
+    let created_entries = diff
+        .changed
+        .iter()
+        .map(|(_, new_entry)| new_entry)
+        .chain(diff.unchanged.iter())
+        .chain(diff.added.iter());
+    for entry in created_entries {
+        plan.create(&entry.source_path, &entry.target_path, entry.deploy, &entry.access)
+            .map_err(|err| Error::SwitchCreate {
+                err,
+                source: entry.source_path.clone(),
+                target: entry.target_path.clone(),
+            })?;
+    }
+
+    plan.finish();
+    execute(&plan, args.dry_run).map_err(Error::SwitchApply)?;
+    record(&plan, &mut manifest, args.dry_run).map_err(Error::SwitchWriteManifest)?;
+    if !args.dry_run {
+        metadata::install(&args.new_metadata_path, &args.metadata_path).map_err(Error::SwitchInstallMetadata)?;
+    }
+    Ok(())
+}
+
+pub fn status(args: command::ArgsStatus) -> Result<(), Error> {
+    let manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::StatusReadManifest)?;
+    let entries = metadata::read_entries(args.metadata_path, args.target_root).map_err(Error::StatusReadMetadata)?;
+    metadata::check_sources(&entries).map_err(Error::StatusReadMetadata)?;
+    metadata::check_overlaps(&entries).map_err(Error::StatusReadMetadata)?;
+    let mut drifted = 0;
     for entry in entries {
-        println!("Removing symlink: {}", entry);
-        symlink::remove(&entry.source_path, &entry.target_path).map_err(|err| Error::LinkRemove {
+        let status = symlink::status(
+            &entry.source_path,
+            &entry.target_path,
+            entry.deploy,
+            &entry.access,
+            &manifest,
+        )
+        .map_err(|err| Error::StatusCheck {
             err,
-            source: entry.source_path,
-            target: entry.target_path,
+            source: entry.source_path.clone(),
+            target: entry.target_path.clone(),
         })?;
+        println!("{}: {}", status, entry);
+        if !status.is_deployed() {
+            drifted += 1;
+        }
     }
-    Ok(())
+    if drifted > 0 {
+        Err(Error::StatusDrifted(drifted))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn unlink(args: command::ArgsUnlink) -> Result<(), Error> {
+    let mut manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::LinkReadManifest)?;
+    let entries = metadata::read_entries(args.metadata_path, args.target_root).map_err(Error::LinkReadMetadata)?;
+    metadata::check_targets(&entries, &manifest).map_err(Error::LinkReadMetadata)?;
+    let mut plan = symlink::Plan::new(symlink::Conflict::Fail, symlink::CrossDevice::Fail, &manifest);
+    for entry in entries {
+        plan.remove(&entry.source_path, &entry.target_path)
+            .map_err(|err| Error::LinkRemove {
+                err,
+                source: entry.source_path,
+                target: entry.target_path,
+            })?;
+    }
+    plan.finish();
+    execute(&plan, args.dry_run).map_err(Error::LinkApply)?;
+    record(&plan, &mut manifest, args.dry_run).map_err(Error::LinkWriteManifest)
+}
+
+pub fn unregister(args: command::ArgsUnregister) -> Result<(), Error> {
+    let target =
+        metadata::registered_target(args.metadata_path.clone(), &args.target).map_err(Error::UnregisterRemoveEntry)?;
+    if let Some(target_root) = args.unlink {
+        let mut manifest = manifest::Manifest::read(&args.metadata_path).map_err(Error::UnregisterReadManifest)?;
+        let target_path = target_root.join(target);
+        let entries =
+            metadata::read_entries(args.metadata_path.clone(), target_root).map_err(Error::UnregisterReadMetadata)?;
+        let mut plan = symlink::Plan::new(symlink::Conflict::Fail, symlink::CrossDevice::Fail, &manifest);
+        for entry in entries.into_iter().filter(|entry| entry.target_path == target_path) {
+            plan.remove(&entry.source_path, &entry.target_path)
+                .map_err(|err| Error::UnregisterRemove {
+                    err,
+                    source: entry.source_path,
+                    target: entry.target_path,
+                })?;
+        }
+        plan.finish();
+        execute(&plan, false).map_err(Error::UnregisterApply)?;
+        record(&plan, &mut manifest, false).map_err(Error::UnregisterWriteManifest)?;
+    }
+    metadata::remove_entry(args.metadata_path, &args.target).map_err(Error::UnregisterRemoveEntry)
+}
+
+pub fn validate(args: command::ArgsValidate) -> Result<(), Error> {
+    metadata::validate(args.metadata_path).map_err(Error::ValidateMetadata)
+}
+
+fn execute(plan: &symlink::Plan, dry_run: bool) -> Result<(), symlink::Error> {
+    if dry_run {
+        for operation in plan.operations() {
+            println!("{}", operation);
+        }
+        return Ok(());
+    }
+    let mut applied = 0;
+    plan.apply(|operation| {
+        println!("{}", operation);
+        applied += 1;
+    })
+    .inspect_err(|_| {
+        if applied > 0 {
+            println!("rollback {applied} operations");
+        }
+    })
+}
+
+fn record(plan: &symlink::Plan, manifest: &mut manifest::Manifest, dry_run: bool) -> Result<(), manifest::Error> {
+    if dry_run {
+        return Ok(());
+    }
+    manifest.record(plan.operations());
+    manifest.write()
 }
 
 #[derive(Debug)]
 pub enum Error {
+    DiffCheck {
+        err: symlink::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    DiffDrifted(usize),
+    DiffReadManifest(manifest::Error),
+    DiffReadMetadata(metadata::Error),
+    DiffTargetNotCopied(PathBuf),
+    ImportMetadata(metadata::Error),
+    LinkApply(symlink::Error),
     LinkCreate {
         err: symlink::Error,
         source: PathBuf,
         target: PathBuf,
     },
+    LinkReadManifest(manifest::Error),
     LinkReadMetadata(metadata::Error),
     LinkRemove {
         err: symlink::Error,
         source: PathBuf,
         target: PathBuf,
     },
+    LinkWriteManifest(manifest::Error),
+    ListJson(metadata::Error),
+    ListReadMetadata(metadata::Error),
+    MigrateMetadata(metadata::Error),
     RegisterNewEntryCreate(metadata::Error),
     RegisterNewEntryWrite(metadata::Error),
+    StatusCheck {
+        err: symlink::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    StatusDrifted(usize),
+    StatusReadManifest(manifest::Error),
+    StatusReadMetadata(metadata::Error),
+    SwitchApply(symlink::Error),
+    SwitchCreate {
+        err: symlink::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    SwitchInstallMetadata(metadata::Error),
+    SwitchReadManifest(manifest::Error),
+    SwitchReadNewMetadata(metadata::Error),
+    SwitchReadOldMetadata(metadata::Error),
+    SwitchRemove {
+        err: symlink::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    SwitchWriteManifest(manifest::Error),
+    UnregisterApply(symlink::Error),
+    UnregisterReadManifest(manifest::Error),
+    UnregisterReadMetadata(metadata::Error),
+    UnregisterRemove {
+        err: symlink::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    UnregisterRemoveEntry(metadata::Error),
+    UnregisterWriteManifest(manifest::Error),
+    ValidateMetadata(metadata::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Self::DiffCheck { source, target, err } => {
+                write!(out, "diff: check {} -> {}: {}", source.display(), target.display(), err)
+            }
+            Self::DiffDrifted(count) => write!(out, "diff: files drifted: {count}"),
+            Self::DiffReadManifest(err) => write!(out, "diff: read manifest: {err}"),
+            Self::DiffReadMetadata(err) => write!(out, "diff: read metadata: {err}"),
+            Self::DiffTargetNotCopied(path) => write!(out, "diff: target is not copied: {}", path.display()),
+            Self::ImportMetadata(err) => write!(out, "import: {err}"),
+            Self::LinkApply(err) => write!(out, "link: apply: {err}"),
             Self::LinkCreate { source, target, err } => write!(
                 out,
                 "link: create {} -> {}: {}",
@@ -61,6 +369,7 @@ impl fmt::Display for Error {
                 target.display(),
                 err
             ),
+            Self::LinkReadManifest(err) => write!(out, "link: read manifest: {err}"),
             Self::LinkReadMetadata(err) => write!(out, "link: read metadata: {err}"),
             Self::LinkRemove { source, target, err } => write!(
                 out,
@@ -69,8 +378,55 @@ impl fmt::Display for Error {
                 target.display(),
                 err
             ),
+            Self::LinkWriteManifest(err) => write!(out, "link: write manifest: {err}"),
+            Self::ListJson(err) => write!(out, "list: encode json: {err}"),
+            Self::ListReadMetadata(err) => write!(out, "list: read metadata: {err}"),
+            Self::MigrateMetadata(err) => write!(out, "migrate: {err}"),
             Self::RegisterNewEntryCreate(err) => write!(out, "register: create new entry: {err}"),
             Self::RegisterNewEntryWrite(err) => write!(out, "register: write new entry: {err}"),
+            Self::StatusCheck { source, target, err } => write!(
+                out,
+                "status: check {} -> {}: {}",
+                source.display(),
+                target.display(),
+                err
+            ),
+            Self::StatusDrifted(count) => write!(out, "status: entries drifted: {count}"),
+            Self::StatusReadManifest(err) => write!(out, "status: read manifest: {err}"),
+            Self::StatusReadMetadata(err) => write!(out, "status: read metadata: {err}"),
+            Self::SwitchApply(err) => write!(out, "switch: apply: {err}"),
+            Self::SwitchCreate { source, target, err } => write!(
+                out,
+                "switch: create {} -> {}: {}",
+                source.display(),
+                target.display(),
+                err
+            ),
+            Self::SwitchInstallMetadata(err) => write!(out, "switch: install metadata: {err}"),
+            Self::SwitchReadManifest(err) => write!(out, "switch: read manifest: {err}"),
+            Self::SwitchReadNewMetadata(err) => write!(out, "switch: read new metadata: {err}"),
+            Self::SwitchReadOldMetadata(err) => write!(out, "switch: read old metadata: {err}"),
+            Self::SwitchRemove { source, target, err } => write!(
+                out,
+                "switch: remove {} -> {}: {}",
+                source.display(),
+                target.display(),
+                err
+            ),
+            Self::SwitchWriteManifest(err) => write!(out, "switch: write manifest: {err}"),
+            Self::UnregisterApply(err) => write!(out, "unregister: apply: {err}"),
+            Self::UnregisterReadManifest(err) => write!(out, "unregister: read manifest: {err}"),
+            Self::UnregisterReadMetadata(err) => write!(out, "unregister: read metadata: {err}"),
+            Self::UnregisterRemove { source, target, err } => write!(
+                out,
+                "unregister: remove {} -> {}: {}",
+                source.display(),
+                target.display(),
+                err
+            ),
+            Self::UnregisterRemoveEntry(err) => write!(out, "unregister: remove entry: {err}"),
+            Self::UnregisterWriteManifest(err) => write!(out, "unregister: write manifest: {err}"),
+            Self::ValidateMetadata(err) => write!(out, "validate: {err}"),
         }
     }
 }
@@ -78,11 +434,41 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         Some(match self {
+            Self::DiffCheck { err, .. } => err,
+            Self::DiffDrifted(_) | Self::DiffTargetNotCopied(_) => return None,
+            Self::DiffReadManifest(err) => err,
+            Self::DiffReadMetadata(err) => err,
+            Self::ImportMetadata(err) => err,
+            Self::LinkApply(err) => err,
             Self::LinkCreate { err, .. } => err,
+            Self::LinkReadManifest(err) => err,
             Self::LinkReadMetadata(err) => err,
             Self::LinkRemove { err, .. } => err,
+            Self::LinkWriteManifest(err) => err,
+            Self::ListJson(err) => err,
+            Self::ListReadMetadata(err) => err,
+            Self::MigrateMetadata(err) => err,
             Self::RegisterNewEntryCreate(err) => err,
             Self::RegisterNewEntryWrite(err) => err,
+            Self::StatusCheck { err, .. } => err,
+            Self::StatusDrifted(_) => return None,
+            Self::StatusReadManifest(err) => err,
+            Self::StatusReadMetadata(err) => err,
+            Self::SwitchApply(err) => err,
+            Self::SwitchCreate { err, .. } => err,
+            Self::SwitchInstallMetadata(err) => err,
+            Self::SwitchReadManifest(err) => err,
+            Self::SwitchReadNewMetadata(err) => err,
+            Self::SwitchReadOldMetadata(err) => err,
+            Self::SwitchRemove { err, .. } => err,
+            Self::SwitchWriteManifest(err) => err,
+            Self::UnregisterApply(err) => err,
+            Self::UnregisterReadManifest(err) => err,
+            Self::UnregisterReadMetadata(err) => err,
+            Self::UnregisterRemove { err, .. } => err,
+            Self::UnregisterRemoveEntry(err) => err,
+            Self::UnregisterWriteManifest(err) => err,
+            Self::ValidateMetadata(err) => err,
         })
     }
 }
@@ -0,0 +1,304 @@
+use std::{error, fmt};
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Null => write!(out, "null"),
+            Self::Bool(value) => write!(out, "{value}"),
+            Self::Number(value) => write!(out, "{value}"),
+            Self::String(value) => write_string(out, value),
+            Self::Array(items) => {
+                write!(out, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(out, ",")?;
+                    }
+                    write!(out, "{item}")?;
+                }
+                write!(out, "]")
+            }
+            Self::Object(fields) => {
+                write!(out, "{{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(out, ",")?;
+                    }
+                    write_string(out, key)?;
+                    write!(out, ":{value}")?;
+                }
+                write!(out, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(out, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            '\u{0}'..='\u{1f}' => write!(out, "\\u{:04x}", c as u32)?,
+            _ => write!(out, "{c}")?,
+        }
+    }
+    write!(out, "\"")
+}
+
+pub fn parse(data: &[u8]) -> Result<Value, Error> {
+    let mut parser = Parser { data, offset: 0 };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.offset < data.len() {
+        return Err(parser.unexpected("end of input"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.offset).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.offset += 1;
+        }
+    }
+
+    fn unexpected(&self, expected: &'static str) -> Error {
+        if self.offset < self.data.len() {
+            Error::Unexpected {
+                offset: self.offset,
+                expected,
+            }
+        } else {
+            Error::UnexpectedEnd { expected }
+        }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), Error> {
+        if self.peek() == Some(byte) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.parse_literal("null", Value::Null),
+            Some(b't') => self.parse_literal("true", Value::Bool(true)),
+            Some(b'f') => self.parse_literal("false", Value::Bool(false)),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'[') => self.parse_array(),
+            Some(b'{') => self.parse_object(),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            _ => Err(self.unexpected("value")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &'static str, value: Value) -> Result<Value, Error> {
+        if self.data[self.offset..].starts_with(literal.as_bytes()) {
+            self.offset += literal.len();
+            Ok(value)
+        } else {
+            Err(self.unexpected(literal))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let start = self.offset;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.offset += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.offset])
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(Value::Number)
+            .ok_or(Error::InvalidNumber { offset: start })
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        let start = self.offset;
+        self.expect(b'"', "string")?;
+        let mut result = Vec::new();
+        loop {
+            let byte = self.peek().ok_or(Error::UnexpectedEnd { expected: "'\"'" })?;
+            self.offset += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek().ok_or(Error::UnexpectedEnd { expected: "escape" })?;
+                    self.offset += 1;
+                    match escape {
+                        b'"' => result.push(b'"'),
+                        b'\\' => result.push(b'\\'),
+                        b'/' => result.push(b'/'),
+                        b'b' => result.push(0x08),
+                        b'f' => result.push(0x0c),
+                        b'n' => result.push(b'\n'),
+                        b'r' => result.push(b'\r'),
+                        b't' => result.push(b'\t'),
+                        b'u' => {
+                            let value = self.parse_unicode_escape()?;
+                            let mut buf = [0; 4];
+                            result.extend_from_slice(value.encode_utf8(&mut buf).as_bytes());
+                        }
+                        _ => {
+                            return Err(Error::InvalidString {
+                                offset: self.offset - 2,
+                            })
+                        }
+                    }
+                }
+                0x00..=0x1f => {
+                    return Err(Error::InvalidString {
+                        offset: self.offset - 1,
+                    })
+                }
+                _ => result.push(byte),
+            }
+        }
+        String::from_utf8(result).map_err(|_| Error::InvalidString { offset: start })
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char, Error> {
+        let offset = self.offset - 2;
+        let high = self.parse_hex()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.data[self.offset..].starts_with(b"\\u") {
+                return Err(Error::InvalidString { offset });
+            }
+            self.offset += 2;
+            let low = self.parse_hex()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(Error::InvalidString { offset });
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or(Error::InvalidString { offset })
+    }
+
+    fn parse_hex(&mut self) -> Result<u32, Error> {
+        let offset = self.offset;
+        let value = self
+            .data
+            .get(offset..offset + 4)
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .and_then(|value| u32::from_str_radix(value, 16).ok())
+            .ok_or(Error::InvalidString { offset })?;
+        self.offset += 4;
+        Ok(value)
+    }
+
+    fn parse_array(&mut self) -> Result<Value, Error> {
+        self.expect(b'[', "'['")?;
+        let mut result = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Value::Array(result));
+        }
+        loop {
+            result.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b']') => {
+                    self.offset += 1;
+                    return Ok(Value::Array(result));
+                }
+                _ => return Err(self.unexpected("',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, Error> {
+        self.expect(b'{', "'{'")?;
+        let mut result = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Value::Object(result));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':', "':'")?;
+            let value = self.parse_value()?;
+            result.push((key, value));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(Value::Object(result));
+                }
+                _ => return Err(self.unexpected("',' or '}'")),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidNumber { offset: usize },
+    InvalidString { offset: usize },
+    Unexpected { offset: usize, expected: &'static str },
+    UnexpectedEnd { expected: &'static str },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidNumber { offset } => write!(out, "invalid number at offset {offset}"),
+            Self::InvalidString { offset } => write!(out, "invalid string at offset {offset}"),
+            Self::Unexpected { offset, expected } => {
+                write!(out, "unexpected character at offset {offset}, expected {expected}")
+            }
+            Self::UnexpectedEnd { expected } => write!(out, "unexpected end of input, expected {expected}"),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -1,8 +1,15 @@
 mod app;
+mod atomic;
 mod command;
+mod escape;
 mod handler;
+mod json;
+mod manifest;
 mod metadata;
+mod sha256;
 mod symlink;
+mod template;
+mod unified;
 
 pub use self::app::{run, Status};
 
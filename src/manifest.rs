@@ -0,0 +1,243 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error,
+    ffi::OsString,
+    fmt,
+    fs::{read, read_link, remove_file},
+    io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{atomic, escape, symlink};
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    path: PathBuf,
+    backups: BTreeMap<PathBuf, PathBuf>,
+    links: BTreeMap<PathBuf, PathBuf>,
+    copies: BTreeMap<PathBuf, String>,
+    hardlinks: BTreeMap<PathBuf, String>,
+    directories: BTreeSet<PathBuf>,
+    changed: bool,
+}
+
+impl Manifest {
+    pub fn read(metadata_path: &Path) -> Result<Self, Error> {
+        let mut path = metadata_path.as_os_str().to_owned();
+        path.push(".manifest");
+        let mut result = Self {
+            path: PathBuf::from(path),
+            ..Self::default()
+        };
+        let data = match read(&result.path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(result),
+            Err(err) => return Err(Error::Read(err)),
+        };
+        for (idx, line) in data.split(|x| *x == b'\n').enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split(|x| *x == b'\t');
+            let kind = fields.next().unwrap_or_default();
+            let paths = fields.map(unescape).collect::<Option<Vec<PathBuf>>>();
+            match (kind, paths.as_deref()) {
+                (b"backup", Some([target, backup])) => {
+                    result.backups.insert(target.clone(), backup.clone());
+                }
+                (b"copy", Some([target, hash])) => {
+                    result
+                        .copies
+                        .insert(target.clone(), hash.to_string_lossy().into_owned());
+                }
+                (b"directory", Some([path])) => {
+                    result.directories.insert(path.clone());
+                }
+                (b"hardlink", Some([target, id])) => {
+                    result
+                        .hardlinks
+                        .insert(target.clone(), id.to_string_lossy().into_owned());
+                }
+                (b"link", Some([target, source])) => {
+                    result.links.insert(target.clone(), source.clone());
+                }
+                _ => {
+                    return Err(Error::Parse {
+                        path: result.path,
+                        line: idx + 1,
+                    })
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn backups(&self) -> &BTreeMap<PathBuf, PathBuf> {
+        &self.backups
+    }
+
+    pub fn links(&self) -> &BTreeMap<PathBuf, PathBuf> {
+        &self.links
+    }
+
+    pub fn copies(&self) -> &BTreeMap<PathBuf, String> {
+        &self.copies
+    }
+
+    pub fn hardlinks(&self) -> &BTreeMap<PathBuf, String> {
+        &self.hardlinks
+    }
+
+    pub fn directories(&self) -> &BTreeSet<PathBuf> {
+        &self.directories
+    }
+
+    pub fn record(&mut self, operations: &[symlink::Operation]) {
+        for operation in operations {
+            match operation {
+                symlink::Operation::Backup { target, backup } => {
+                    self.backups.insert(target.clone(), backup.clone());
+                    self.changed = true;
+                }
+                symlink::Operation::CopyFile { target, hash, .. }
+                | symlink::Operation::RenderTemplate { target, hash, .. } => {
+                    self.copies.insert(target.clone(), hash.clone());
+                    self.changed = true;
+                }
+                symlink::Operation::CreateHardlink { target, id, .. } => {
+                    self.hardlinks.insert(target.clone(), id.clone());
+                    self.changed = true;
+                }
+                symlink::Operation::CreateSymlink { source, target }
+                | symlink::Operation::ReplaceSymlink { source, target } => {
+                    self.links.insert(target.clone(), source.clone());
+                    self.changed = true;
+                }
+                symlink::Operation::CreateDirectory { path, .. } => {
+                    self.directories.insert(path.clone());
+                    self.changed = true;
+                }
+                symlink::Operation::RemoveCopy(target) => {
+                    self.copies.remove(target);
+                    self.changed = true;
+                }
+                symlink::Operation::RemoveDirectory(path) => {
+                    self.directories.remove(path);
+                    self.changed = true;
+                }
+                symlink::Operation::RemoveHardlink(target) => {
+                    self.hardlinks.remove(target);
+                    self.changed = true;
+                }
+                symlink::Operation::RemoveSymlink(target) => {
+                    self.links.remove(target);
+                    self.changed = true;
+                }
+                symlink::Operation::RestoreBackup { target, .. } => {
+                    self.backups.remove(target);
+                    self.changed = true;
+                }
+                symlink::Operation::Overwrite(_)
+                | symlink::Operation::SetMode { .. }
+                | symlink::Operation::SetOwner { .. } => {}
+            }
+        }
+        let links_count = self.links.len();
+        let copies_count = self.copies.len();
+        let hardlinks_count = self.hardlinks.len();
+        let directories_count = self.directories.len();
+        self.links
+            .retain(|target, source| read_link(target).is_ok_and(|link| link == *source));
+        self.copies.retain(|target, _| target.is_file() && !target.is_symlink());
+        self.hardlinks
+            .retain(|target, _| target.is_file() && !target.is_symlink());
+        self.directories.retain(|path| path.is_dir() && !path.is_symlink());
+        if self.links.len() != links_count
+            || self.copies.len() != copies_count
+            || self.hardlinks.len() != hardlinks_count
+            || self.directories.len() != directories_count
+        {
+            self.changed = true;
+        }
+    }
+
+    pub fn write(&self) -> Result<(), Error> {
+        if !self.changed {
+            return Ok(());
+        }
+        if self.backups.is_empty()
+            && self.links.is_empty()
+            && self.copies.is_empty()
+            && self.hardlinks.is_empty()
+            && self.directories.is_empty()
+        {
+            return match remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::Write(err)),
+                _ => Ok(()),
+            };
+        }
+        let mut data = Vec::new();
+        for (target, backup) in &self.backups {
+            write_record(&mut data, b"backup", target, backup);
+        }
+        for (target, hash) in &self.copies {
+            write_record(&mut data, b"copy", target, Path::new(hash));
+        }
+        for (target, id) in &self.hardlinks {
+            write_record(&mut data, b"hardlink", target, Path::new(id));
+        }
+        for path in &self.directories {
+            data.extend_from_slice(b"directory\t");
+            escape(path, &mut data);
+            data.push(b'\n');
+        }
+        for (target, source) in &self.links {
+            write_record(&mut data, b"link", target, source);
+        }
+        atomic::write(&self.path, &data).map_err(Error::Write)
+    }
+}
+
+fn write_record(out: &mut Vec<u8>, kind: &[u8], target: &Path, value: &Path) {
+    out.extend_from_slice(kind);
+    out.push(b'\t');
+    escape(target, out);
+    out.push(b'\t');
+    escape(value, out);
+    out.push(b'\n');
+}
+
+fn escape(path: &Path, out: &mut Vec<u8>) {
+    escape::encode(path.as_os_str().as_bytes(), out);
+}
+
+fn unescape(value: &[u8]) -> Option<PathBuf> {
+    escape::decode(value).map(|value| PathBuf::from(OsString::from_vec(value)))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse { path: PathBuf, line: usize },
+    Read(io::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse { path, line } => write!(out, "parse manifest: {}: line {}", path.display(), line),
+            Self::Read(err) => write!(out, "read manifest: {err}"),
+            Self::Write(err) => write!(out, "write manifest: {err}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(match self {
+            Self::Parse { .. } => return None,
+            Self::Read(err) | Self::Write(err) => err,
+        })
+    }
+}
@@ -1,23 +1,144 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap},
     error,
+    ffi::{OsStr, OsString},
     fmt,
-    fs::File,
-    io::{self, BufRead, BufReader, Lines, Write},
-    path::{Path, PathBuf},
+    fs::{canonicalize, read, read_to_string, File},
+    io::{self, BufRead, BufReader, Split},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Component, Path, PathBuf},
 };
 
+use crate::{
+    atomic,
+    escape,
+    json,
+    manifest::Manifest,
+    symlink::{Access, Deploy},
+};
+
+const HEADER: &[u8] = b"makky-metadata";
+const VERSION: &[u8] = b"1";
+const ENTRY_END: &[u8] = b"end";
+const TRAILER: &[u8] = b"end-metadata";
+const INCLUDE: &str = "include";
+const DEPLOY: &str = "deploy";
+const MODE: &str = "mode";
+const DIR_MODE: &str = "dir_mode";
+const OWNER: &str = "owner";
+const GROUP: &str = "group";
+
 pub fn write_entry(config_path: PathBuf, new_entry: &NewEntry) -> Result<(), Error> {
-    let mut config_file = File::options()
-        .create(true)
-        .append(true)
-        .open(config_path)
-        .map_err(Error::OpenConfig)?;
+    let _lock = atomic::lock(&config_path).map_err(Error::LockConfig)?;
+
+    let mut data = match read(&config_path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(Error::OpenConfig(err)),
+    };
+    if data.is_empty() {
+        serialize_header(&mut data);
+        new_entry.serialize(&mut data);
+        serialize_trailer(&mut data, 1);
+    } else {
+        let mut config_parser = ConfigParser::new(config_path.clone())?;
+        if matches!(config_parser.format, Format::Legacy) {
+            return Err(Error::UnsupportedFormat(config_path));
+        }
+        let count = config_parser.by_ref().collect::<Result<Vec<Record>, Error>>()?.len();
+        let trailer = config_parser
+            .trailer
+            .ok_or_else(|| config_parser.error(Error::ParseTrailerMissing))?;
+        let mut new_entries = Vec::new();
+        read_new_entries(config_path.clone(), &mut Vec::new(), &mut new_entries)?;
+        let registered = new_entries
+            .into_iter()
+            .find(|(_, registered_entry)| registered_entry.target == new_entry.target);
+        if let Some((location, _)) = registered {
+            return Err(Error::Located {
+                err: Box::new(Error::NewEntryTargetRegistered(PathBuf::from(&new_entry.target))),
+                location,
+            });
+        }
+        let lines: Vec<&[u8]> = data.split(|x| *x == b'\n').collect();
+        let mut result = Vec::new();
+        for line in &lines[..trailer - 1] {
+            result.extend_from_slice(line);
+            result.push(b'\n');
+        }
+        new_entry.serialize(&mut result);
+        serialize_trailer(&mut result, count + 1);
+        result.extend_from_slice(&lines[trailer..].join(&b'\n'));
+        data = result;
+    }
+    atomic::write(&config_path, &data).map_err(Error::WriteNewEntry)
+}
+
+pub fn import(config_path: PathBuf, json_path: &Path) -> Result<(), Error> {
+    let data = read(json_path).map_err(Error::ImportRead)?;
+    let value = json::parse(&data).map_err(Error::ImportParse)?;
+    let json::Value::Array(items) = value else {
+        return Err(Error::ImportNotArray);
+    };
+
+    let mut new_entries: Vec<NewEntry> = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let new_entry = NewEntry::from_json(item).and_then(|new_entry| {
+            if new_entries.iter().any(|x| x.target == new_entry.target) {
+                Err(Error::NewEntryTargetRegistered(PathBuf::from(new_entry.target)))
+            } else {
+                Ok(new_entry)
+            }
+        });
+        match new_entry {
+            Ok(new_entry) => new_entries.push(new_entry),
+            Err(err) => errors.push(Error::ImportItem {
+                err: Box::new(err),
+                index,
+            }),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::ParseEntries(errors));
+    }
 
-    let new_entry_data = new_entry.serialize();
-    config_file
-        .write_fmt(format_args!("{new_entry_data}"))
-        .map_err(Error::WriteNewEntry)
+    let _lock = atomic::lock(&config_path).map_err(Error::LockConfig)?;
+    let mut data = Vec::new();
+    serialize_header(&mut data);
+    for new_entry in &new_entries {
+        new_entry.serialize(&mut data);
+    }
+    serialize_trailer(&mut data, new_entries.len());
+    atomic::write(&config_path, &data).map_err(Error::WriteEntries)
+}
+
+pub fn migrate(config_path: PathBuf) -> Result<(), Error> {
+    let mut config_parser = ConfigParser::new(config_path.clone())?;
+    config_parser.trailer_optional = true;
+    let records = config_parser.collect::<Result<Vec<Record>, Error>>()?;
+    let mut data = Vec::new();
+    serialize_header(&mut data);
+    for record in &records {
+        match record {
+            Record::Entry(_, new_entry) => new_entry.serialize(&mut data),
+            Record::Include(_, path) => serialize_attribute(&mut data, INCLUDE, path.as_os_str().as_bytes()),
+        }
+    }
+    serialize_trailer(&mut data, records.len());
+    atomic::write(&config_path, &data).map_err(Error::WriteEntries)
+}
+
+pub fn install(source_path: &Path, target_path: &Path) -> Result<(), Error> {
+    let mut new_entries = Vec::new();
+    read_new_entries(source_path.to_owned(), &mut Vec::new(), &mut new_entries)?;
+    let mut data = Vec::new();
+    serialize_header(&mut data);
+    for (_, new_entry) in &new_entries {
+        new_entry.serialize(&mut data);
+    }
+    serialize_trailer(&mut data, new_entries.len());
+    atomic::write(target_path, &data).map_err(Error::InstallWrite)
 }
 
 pub fn read_entries(config_path: PathBuf, target_root: PathBuf) -> Result<Vec<Entry>, Error> {
@@ -28,21 +149,27 @@ pub fn read_entries(config_path: PathBuf, target_root: PathBuf) -> Result<Vec<En
         return Err(Error::TargetRootNotADirectory(target_root));
     }
 
-    let config_parser = ConfigParser::new(config_path)?;
+    let mut new_entries = Vec::new();
+    read_new_entries(config_path, &mut Vec::new(), &mut new_entries)?;
 
     let mut result = Vec::new();
-    let mut seen_targets: HashSet<String> = HashSet::new();
+    let mut seen_targets: HashMap<OsString, Location> = HashMap::new();
     let mut errors: Vec<Error> = Vec::new();
-    for raw_entry in config_parser {
-        let (source, target) = raw_entry?;
-
-        if seen_targets.contains(&target) {
-            errors.push(Error::EntryTargetDuplicate { source, target });
+    for (location, new_entry) in new_entries {
+        if let Some(previous) = seen_targets.get(&new_entry.target) {
+            errors.push(Error::Located {
+                err: Box::new(Error::EntryTargetDuplicate {
+                    source: PathBuf::from(new_entry.source),
+                    target: PathBuf::from(new_entry.target),
+                    previous: previous.clone(),
+                }),
+                location,
+            });
             continue;
         }
-        seen_targets.insert(target.clone());
+        seen_targets.insert(new_entry.target.clone(), location.clone());
 
-        match Entry::create(source, target, &target_root) {
+        match Entry::create(new_entry, location, &target_root) {
             Ok(entry) => result.push(entry),
             Err(err) => errors.push(err),
         }
@@ -55,14 +182,248 @@ pub fn read_entries(config_path: PathBuf, target_root: PathBuf) -> Result<Vec<En
     }
 }
 
+pub fn list(config_path: PathBuf) -> Result<Vec<NewEntry>, Error> {
+    let mut new_entries = Vec::new();
+    read_new_entries(config_path, &mut Vec::new(), &mut new_entries)?;
+    Ok(new_entries.into_iter().map(|(_, new_entry)| new_entry).collect())
+}
+
+pub fn registered_target(config_path: PathBuf, target: &OsStr) -> Result<PathBuf, Error> {
+    let not_registered = || Error::EntryNotRegistered(PathBuf::from(target));
+    let normalized = normalize(Path::new(target))
+        .filter(|normalized| !normalized.as_os_str().is_empty())
+        .ok_or_else(not_registered)?;
+    let mut new_entries = Vec::new();
+    read_new_entries(config_path.clone(), &mut Vec::new(), &mut new_entries)?;
+    let (location, _) = new_entries
+        .into_iter()
+        .find(|(_, new_entry)| normalize(Path::new(&new_entry.target)).as_ref() == Some(&normalized))
+        .ok_or_else(not_registered)?;
+    if location.path != config_path {
+        return Err(Error::Located {
+            err: Box::new(Error::EntryIncluded(PathBuf::from(target))),
+            location,
+        });
+    }
+    Ok(normalized)
+}
+
+pub fn remove_entry(config_path: PathBuf, target: &OsStr) -> Result<(), Error> {
+    let _lock = atomic::lock(&config_path).map_err(Error::LockConfig)?;
+
+    let normalized = registered_target(config_path.clone(), target)?;
+    let mut config_parser = ConfigParser::new(config_path.clone())?;
+    if matches!(config_parser.format, Format::Legacy) {
+        return Err(Error::UnsupportedFormat(config_path));
+    }
+    let records = config_parser.by_ref().collect::<Result<Vec<Record>, Error>>()?;
+    let trailer = config_parser
+        .trailer
+        .ok_or_else(|| config_parser.error(Error::ParseTrailerMissing))?;
+    let location = records
+        .iter()
+        .find_map(|record| match record {
+            Record::Entry(location, new_entry)
+                if normalize(Path::new(&new_entry.target)).as_ref() == Some(&normalized) =>
+            {
+                Some(location)
+            }
+            _ => None,
+        })
+        .ok_or_else(|| Error::EntryNotRegistered(PathBuf::from(target)))?;
+
+    let data = read(&config_path).map_err(Error::OpenConfig)?;
+    let trailer_line = trailer_line(records.len() - 1);
+    let mut lines: Vec<&[u8]> = data.split(|x| *x == b'\n').collect();
+    lines[trailer - 1] = &trailer_line;
+    let start = location.line - 1;
+    let end = lines[start..]
+        .iter()
+        .position(|line| *line == ENTRY_END)
+        .map_or(lines.len(), |idx| start + idx + 1);
+    let data = [&lines[..start], &lines[end..]].concat().join(&b'\n');
+    atomic::write(&config_path, &data).map_err(Error::WriteEntries)
+}
+
+pub fn validate(config_path: PathBuf) -> Result<(), Error> {
+    let mut new_entries = Vec::new();
+    read_new_entries(config_path, &mut Vec::new(), &mut new_entries)?;
+
+    let mut seen_targets: HashMap<&OsStr, &Location> = HashMap::new();
+    let mut normalized_targets: Vec<(PathBuf, Location)> = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+    for (location, new_entry) in &new_entries {
+        let located = |err| Error::Located {
+            err: Box::new(err),
+            location: location.clone(),
+        };
+        let source = Path::new(&new_entry.source);
+        if !source.is_absolute() {
+            errors.push(located(Error::NewEntrySourceNotAbsolute(source.to_owned())));
+        }
+        if let Err(err) = new_entry.deploy() {
+            errors.push(located(err));
+        }
+        for key in [MODE, DIR_MODE] {
+            if let Err(err) = new_entry.mode(key) {
+                errors.push(located(err));
+            }
+        }
+        let target = Path::new(&new_entry.target);
+        if target.is_absolute() {
+            errors.push(located(Error::NewEntryTargetIsAbsolute(target.to_owned())));
+            continue;
+        }
+        let Some(normalized) = normalize(target) else {
+            errors.push(located(Error::EntryTargetEscapesRoot(target.to_owned())));
+            continue;
+        };
+        if normalized.as_os_str().is_empty() {
+            errors.push(located(Error::EntryTargetEmpty(target.to_owned())));
+            continue;
+        }
+        if let Some(previous) = seen_targets.get(new_entry.target.as_os_str()) {
+            errors.push(located(Error::EntryTargetDuplicate {
+                source: source.to_owned(),
+                target: target.to_owned(),
+                previous: (*previous).clone(),
+            }));
+            continue;
+        }
+        seen_targets.insert(&new_entry.target, location);
+        match find_overlap(&normalized_targets, &normalized, target) {
+            Some(err) => errors.push(located(err)),
+            None => normalized_targets.push((normalized, location.clone())),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ParseEntries(errors))
+    }
+}
+
+fn find_overlap(seen_targets: &[(PathBuf, Location)], normalized: &Path, target: &Path) -> Option<Error> {
+    let (other, previous) = seen_targets
+        .iter()
+        .find(|(other, _)| other.starts_with(normalized) || normalized.starts_with(other))?;
+    let target = target.to_owned();
+    let previous = previous.clone();
+    Some(if other == normalized {
+        Error::EntryTargetEquivalent { target, previous }
+    } else {
+        Error::EntryTargetOverlap {
+            target,
+            other: other.clone(),
+            previous,
+        }
+    })
+}
+
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            }
+            component => result.push(component),
+        }
+    }
+    Some(result)
+}
+
+fn read_new_entries(
+    config_path: PathBuf,
+    parents: &mut Vec<PathBuf>,
+    result: &mut Vec<(Location, NewEntry)>,
+) -> Result<(), Error> {
+    let config_parser = ConfigParser::new(config_path.clone())?;
+    let config_directory = config_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parents.push(canonicalize(&config_path).unwrap_or(config_path));
+    for record in config_parser {
+        match record? {
+            Record::Entry(location, new_entry) => result.push((location, new_entry)),
+            Record::Include(location, path) => {
+                let path = config_directory.join(path);
+                let located = |err| Error::Located {
+                    err: Box::new(err),
+                    location: location.clone(),
+                };
+                if canonicalize(&path).is_ok_and(|path| parents.contains(&path)) {
+                    return Err(located(Error::IncludeCycle(path)));
+                }
+                read_new_entries(path, parents, result).map_err(|err| match err {
+                    Error::OpenConfig(_) => located(err),
+                    err => err,
+                })?;
+            }
+        }
+    }
+    parents.pop();
+    Ok(())
+}
+
+pub fn check_sources(entries: &[Entry]) -> Result<(), Error> {
+    let errors: Vec<Error> = entries
+        .iter()
+        .filter(|entry| !entry.source_path.exists())
+        .map(|entry| entry.error(Error::EntrySourceNotExists(entry.source_path.clone())))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ParseEntries(errors))
+    }
+}
+
+pub fn check_overlaps(entries: &[Entry]) -> Result<(), Error> {
+    let mut seen_targets: Vec<(PathBuf, Location)> = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+    for entry in entries {
+        match find_overlap(&seen_targets, &entry.target_path, &entry.target_path) {
+            Some(err) => errors.push(entry.error(err)),
+            None => seen_targets.push((entry.target_path.clone(), entry.location.clone())),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ParseEntries(errors))
+    }
+}
+
+pub fn check_targets(entries: &[Entry], manifest: &Manifest) -> Result<(), Error> {
+    let errors: Vec<Error> = entries
+        .iter()
+        .filter(|entry| entry.target_path.exists() && !entry.target_path.is_symlink() && entry.target_path.is_file())
+        .filter(|entry| {
+            !manifest.copies().contains_key(&entry.target_path)
+                && !manifest.hardlinks().contains_key(&entry.target_path)
+        })
+        .map(|entry| entry.error(Error::EntryTargetExists(entry.target_path.clone())))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ParseEntries(errors))
+    }
+}
+
 #[derive(Debug)]
 pub struct NewEntry {
-    source: String,
-    target: String,
+    source: OsString,
+    target: OsString,
+    attributes: BTreeMap<String, String>,
 }
 
 impl NewEntry {
-    pub fn create(source: impl Into<String>, target: impl Into<String>) -> Result<Self, Error> {
+    pub fn create(source: impl Into<OsString>, target: impl Into<OsString>) -> Result<Self, Error> {
         let source = source.into();
         let source_path = Path::new(&source);
         if !source_path.is_absolute() {
@@ -74,12 +435,147 @@ impl NewEntry {
         if target_path.is_absolute() {
             return Err(Error::NewEntryTargetIsAbsolute(target_path.to_owned()));
         }
+        if normalize(target_path).is_none() {
+            return Err(Error::EntryTargetEscapesRoot(target_path.to_owned()));
+        }
 
-        Ok(Self { source, target })
+        Ok(Self {
+            source,
+            target,
+            attributes: BTreeMap::new(),
+        })
     }
 
-    fn serialize(&self) -> String {
-        format!("{}\n{}\n", &self.source, &self.target)
+    fn from_json(value: &json::Value) -> Result<Self, Error> {
+        if !matches!(value, json::Value::Object(_)) {
+            return Err(Error::ImportItemNotObject);
+        }
+        let field = |name| {
+            value
+                .get(name)
+                .and_then(json::Value::as_str)
+                .ok_or(Error::ImportItemField(name))
+        };
+        let mut new_entry = Self::create(field("source")?, field("target")?)?;
+        if value.get(DEPLOY).is_some() {
+            let deploy = field(DEPLOY)?.parse().map_err(Error::EntryDeployUnknown)?;
+            new_entry = new_entry.with_deploy(deploy);
+        }
+        let attribute = |key| value.get(key).map(|_| field(key).map(String::from)).transpose();
+        new_entry.with_access(
+            attribute(MODE)?,
+            attribute(DIR_MODE)?,
+            attribute(OWNER)?,
+            attribute(GROUP)?,
+        )
+    }
+
+    pub fn with_access(
+        mut self,
+        mode: Option<String>,
+        dir_mode: Option<String>,
+        owner: Option<String>,
+        group: Option<String>,
+    ) -> Result<Self, Error> {
+        for (key, value) in [(MODE, mode), (DIR_MODE, dir_mode), (OWNER, owner), (GROUP, group)] {
+            if let Some(value) = value {
+                self.attributes.insert(String::from(key), value);
+            }
+        }
+        self.mode(MODE)?;
+        self.mode(DIR_MODE)?;
+        Ok(self)
+    }
+
+    pub fn with_deploy(mut self, deploy: Deploy) -> Self {
+        if deploy == Deploy::default() {
+            self.attributes.remove(DEPLOY);
+        } else {
+            self.attributes.insert(String::from(DEPLOY), deploy.to_string());
+        }
+        self
+    }
+
+    fn deploy(&self) -> Result<Deploy, Error> {
+        self.attributes.get(DEPLOY).map_or(Ok(Deploy::default()), |value| {
+            value.parse().map_err(Error::EntryDeployUnknown)
+        })
+    }
+
+    fn mode(&self, key: &'static str) -> Result<Option<u32>, Error> {
+        self.attributes
+            .get(key)
+            .map(|value| {
+                u32::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o7777 && !value.starts_with('+'))
+                    .ok_or_else(|| Error::EntryModeInvalid {
+                        key,
+                        value: value.clone(),
+                    })
+            })
+            .transpose()
+    }
+
+    fn access(&self) -> Result<Access, Error> {
+        let id = |key, database| {
+            self.attributes
+                .get(key)
+                .map(|value| {
+                    lookup_id(Path::new(database), value).ok_or_else(|| Error::EntryIdUnknown {
+                        key,
+                        value: value.clone(),
+                    })
+                })
+                .transpose()
+        };
+        Ok(Access {
+            mode: self.mode(MODE)?,
+            dir_mode: self.mode(DIR_MODE)?,
+            owner: id(OWNER, "/etc/passwd")?,
+            group: id(GROUP, "/etc/group")?,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<json::Value, Error> {
+        let string = |path: &OsStr| {
+            path.to_str()
+                .map(|path| json::Value::String(String::from(path)))
+                .ok_or_else(|| Error::EntryPathNotUtf8(PathBuf::from(path)))
+        };
+        let mut fields = vec![
+            (String::from("source"), string(&self.source)?),
+            (String::from("target"), string(&self.target)?),
+        ];
+        for (key, value) in &self.attributes {
+            fields.push((key.clone(), json::Value::String(value.clone())));
+        }
+        Ok(json::Value::Object(fields))
+    }
+
+    fn serialize(&self, out: &mut Vec<u8>) {
+        serialize_attribute(out, "source", self.source.as_bytes());
+        serialize_attribute(out, "target", self.target.as_bytes());
+        for (key, value) in &self.attributes {
+            serialize_attribute(out, key, value.as_bytes());
+        }
+        out.extend_from_slice(ENTRY_END);
+        out.push(b'\n');
+    }
+}
+
+impl fmt::Display for NewEntry {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            out,
+            "{} -> {}",
+            Path::new(&self.source).display(),
+            Path::new(&self.target).display()
+        )?;
+        for (key, value) in &self.attributes {
+            write!(out, " {key}={value}")?;
+        }
+        Ok(())
     }
 }
 
@@ -87,23 +583,46 @@ impl NewEntry {
 pub struct Entry {
     pub source_path: PathBuf,
     pub target_path: PathBuf,
+    pub deploy: Deploy,
+    pub access: Access,
+    pub attributes: BTreeMap<String, String>,
+    pub location: Location,
 }
 
 impl Entry {
-    fn create(source: String, target: String, target_root: &Path) -> Result<Self, Error> {
-        let source_path = PathBuf::from(&source);
-        if !source_path.exists() {
-            return Err(Error::EntrySourceNotExists(source_path));
+    fn create(new_entry: NewEntry, location: Location, target_root: &Path) -> Result<Self, Error> {
+        let source_path = PathBuf::from(&new_entry.source);
+        let target = Path::new(&new_entry.target);
+        let located = |err| Error::Located {
+            err: Box::new(err),
+            location: location.clone(),
+        };
+        if target.has_root() {
+            return Err(located(Error::NewEntryTargetIsAbsolute(target.to_owned())));
         }
-        let target_path = target_root.join(target);
-        if target_path.exists() && !target_path.is_symlink() && target_path.is_file() {
-            return Err(Error::EntryTargetExists(target_path));
+        let normalized = normalize(target).ok_or_else(|| located(Error::EntryTargetEscapesRoot(target.to_owned())))?;
+        if normalized.as_os_str().is_empty() {
+            return Err(located(Error::EntryTargetEmpty(target.to_owned())));
         }
+        let target_path = target_root.join(normalized);
+        let deploy = new_entry.deploy().map_err(located)?;
+        let access = new_entry.access().map_err(located)?;
         Ok(Self {
             source_path,
             target_path,
+            deploy,
+            access,
+            attributes: new_entry.attributes,
+            location,
         })
     }
+
+    fn error(&self, err: Error) -> Error {
+        Error::Located {
+            err: Box::new(err),
+            location: self.location.clone(),
+        }
+    }
 }
 
 impl fmt::Display for Entry {
@@ -112,67 +631,442 @@ impl fmt::Display for Entry {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct EntriesDiff {
+    pub removed: Vec<Entry>,
+    pub changed: Vec<(Entry, Entry)>,
+    pub unchanged: Vec<Entry>,
+    pub added: Vec<Entry>,
+}
+
+impl EntriesDiff {
+    pub fn new(old_entries: Vec<Entry>, new_entries: Vec<Entry>) -> Self {
+        let old_targets: Vec<PathBuf> = old_entries.iter().map(|entry| entry.target_path.clone()).collect();
+        let mut old_entries: HashMap<PathBuf, Entry> = old_entries
+            .into_iter()
+            .map(|entry| (entry.target_path.clone(), entry))
+            .collect();
+
+        let mut result = Self::default();
+        for new_entry in new_entries {
+            match old_entries.remove(&new_entry.target_path) {
+                Some(old_entry)
+                    if old_entry.source_path == new_entry.source_path
+                        && old_entry.attributes == new_entry.attributes =>
+                {
+                    result.unchanged.push(new_entry)
+                }
+                Some(old_entry) => result.changed.push((old_entry, new_entry)),
+                None => result.added.push(new_entry),
+            }
+        }
+        for old_target in old_targets {
+            if let Some(old_entry) = old_entries.remove(&old_target) {
+                result.removed.push(old_entry);
+            }
+        }
+        result
+    }
+}
+
+fn lookup_id(database: &Path, name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    read_to_string(database).ok()?.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next() == Some(name)).then(|| fields.nth(1)?.parse().ok())?
+    })
+}
+
+fn header_line() -> Vec<u8> {
+    [HEADER, b"\t", VERSION].concat()
+}
+
+fn serialize_header(out: &mut Vec<u8>) {
+    out.extend_from_slice(&header_line());
+    out.push(b'\n');
+}
+
+fn trailer_line(count: usize) -> Vec<u8> {
+    [TRAILER, b"\t", count.to_string().as_bytes()].concat()
+}
+
+fn serialize_trailer(out: &mut Vec<u8>, count: usize) {
+    out.extend_from_slice(&trailer_line(count));
+    out.push(b'\n');
+}
+
+fn serialize_attribute(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    out.extend_from_slice(key.as_bytes());
+    out.push(b'\t');
+    escape::encode(value, out);
+    out.push(b'\n');
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Legacy,
+    Versioned,
+}
+
+enum Record {
+    Entry(Location, NewEntry),
+    Include(Location, PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(out, "{}:{}", self.path.display(), self.line)
+    }
+}
+
 struct ConfigParser {
-    lines: Lines<BufReader<File>>,
+    path: PathBuf,
+    lines: Split<BufReader<File>>,
+    line: usize,
+    format: Format,
+    pending: Option<Vec<u8>>,
+    records: usize,
+    trailer: Option<usize>,
+    trailer_optional: bool,
 }
 
 impl ConfigParser {
     fn new(path: PathBuf) -> Result<Self, Error> {
-        let file = File::options().read(true).open(path).map_err(Error::OpenConfig)?;
+        let file = File::options().read(true).open(&path).map_err(Error::OpenConfig)?;
         let reader = BufReader::new(file);
-        let lines = reader.lines();
-        Ok(Self { lines })
+        let mut result = Self {
+            path,
+            lines: reader.split(b'\n'),
+            line: 0,
+            format: Format::Legacy,
+            pending: None,
+            records: 0,
+            trailer: None,
+            trailer_optional: false,
+        };
+        let header = result
+            .next_line()
+            .transpose()
+            .map_err(|err| result.error(Error::ParseHeader(err)))?;
+        if let Some(line) = header {
+            match split_attribute(&line) {
+                Some((HEADER, VERSION)) => result.format = Format::Versioned,
+                Some((HEADER, version)) => {
+                    let version = String::from_utf8_lossy(version).into_owned();
+                    return Err(result.error(Error::UnsupportedVersion(version)));
+                }
+                _ => result.pending = Some(line),
+            }
+        }
+        Ok(result)
     }
+
+    fn location(&self) -> Location {
+        Location {
+            path: self.path.clone(),
+            line: self.line,
+        }
+    }
+
+    fn error(&self, err: Error) -> Error {
+        Error::Located {
+            err: Box::new(err),
+            location: self.location(),
+        }
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if let Some(line) = self.pending.take() {
+            return Some(Ok(line));
+        }
+        loop {
+            let line = self.lines.next()?;
+            self.line += 1;
+            match line {
+                Ok(line) if matches!(self.format, Format::Versioned) && (line.is_empty() || line.starts_with(b"#")) => {
+                    continue
+                }
+                line => return Some(line),
+            }
+        }
+    }
+
+    fn next_legacy(&mut self) -> Option<Result<Record, Error>> {
+        let source = match self.next_line()? {
+            Ok(source) => source,
+            Err(err) => return Some(Err(self.error(Error::ParseEntrySource(err)))),
+        };
+        let location = self.location();
+        let target = match self.next_line() {
+            Some(Ok(target)) => target,
+            Some(Err(err)) => return Some(Err(self.error(Error::ParseEntryTarget(err)))),
+            None => {
+                return Some(Err(Error::Located {
+                    err: Box::new(Error::ParseEntryTargetMissing),
+                    location,
+                }))
+            }
+        };
+        let new_entry = NewEntry {
+            source: OsString::from_vec(source),
+            target: OsString::from_vec(target),
+            attributes: BTreeMap::new(),
+        };
+        Some(Ok(Record::Entry(location, new_entry)))
+    }
+
+    fn next_versioned(&mut self) -> Option<Result<Record, Error>> {
+        if self.trailer.is_some() {
+            return None;
+        }
+        let mut attributes = BTreeMap::new();
+        let mut location = None;
+        loop {
+            let line = match self.next_line() {
+                None if location.is_none() && self.trailer_optional => return None,
+                None if location.is_none() => return Some(Err(self.error(Error::ParseTrailerMissing))),
+                None => return Some(Err(self.error(Error::ParseEntryUnterminated))),
+                Some(Err(err)) => return Some(Err(self.error(Error::ParseEntry(err)))),
+                Some(Ok(line)) => line,
+            };
+            if location.is_none() {
+                if let Some((TRAILER, count)) = split_attribute(&line) {
+                    return self.next_trailer(count).err().map(Err);
+                }
+                location = Some(self.location());
+            }
+            if line == ENTRY_END {
+                break;
+            }
+            let attribute = split_attribute(&line).and_then(|(key, value)| {
+                let key = String::from_utf8(key.to_vec()).ok()?;
+                let value = escape::decode(value)?;
+                Some((key, value))
+            });
+            let Some((key, value)) = attribute else {
+                let line = String::from_utf8_lossy(&line).into_owned();
+                return Some(Err(self.error(Error::ParseEntryAttributeInvalid(line))));
+            };
+            if key == INCLUDE && attributes.is_empty() {
+                let path = PathBuf::from(OsString::from_vec(value));
+                self.records += 1;
+                return Some(Ok(Record::Include(self.location(), path)));
+            }
+            if attributes.insert(key.clone(), value).is_some() {
+                return Some(Err(self.error(Error::ParseEntryAttributeDuplicate(key))));
+            }
+        }
+        let location = location?;
+        let located = |err| Error::Located {
+            err: Box::new(err),
+            location: location.clone(),
+        };
+        let Some(source) = attributes.remove("source") else {
+            return Some(Err(located(Error::ParseEntrySourceMissing)));
+        };
+        let Some(target) = attributes.remove("target") else {
+            return Some(Err(located(Error::ParseEntryTargetMissing)));
+        };
+        let attributes = attributes
+            .into_iter()
+            .map(|(key, value)| match String::from_utf8(value) {
+                Ok(value) => Ok((key, value)),
+                Err(_) => Err(located(Error::ParseEntryAttributeInvalid(key))),
+            })
+            .collect::<Result<BTreeMap<String, String>, Error>>();
+        Some(attributes.map(|attributes| {
+            let new_entry = NewEntry {
+                source: OsString::from_vec(source),
+                target: OsString::from_vec(target),
+                attributes,
+            };
+            self.records += 1;
+            Record::Entry(location.clone(), new_entry)
+        }))
+    }
+
+    fn next_trailer(&mut self, count: &[u8]) -> Result<(), Error> {
+        self.trailer = Some(self.line);
+        let expected = std::str::from_utf8(count)
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .filter(|_| !count.starts_with(b"+"))
+            .ok_or_else(|| self.error(Error::ParseTrailerInvalid(String::from_utf8_lossy(count).into_owned())))?;
+        if expected != self.records {
+            return Err(self.error(Error::ParseTrailerCount {
+                expected,
+                actual: self.records,
+            }));
+        }
+        if self.next_line().is_some() {
+            return Err(self.error(Error::ParseTrailerNotLast));
+        }
+        Ok(())
+    }
+}
+
+fn split_attribute(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let idx = line.iter().position(|x| *x == b'\t')?;
+    Some((&line[..idx], &line[idx + 1..]))
 }
 
 impl Iterator for ConfigParser {
-    type Item = Result<(String, String), Error>;
+    type Item = Result<Record, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.lines.next().map(|source| {
-            source.map_err(Error::ParseEntrySource).and_then(|source| {
-                self.lines
-                    .next()
-                    .map(|target| target.map_err(Error::ParseEntryTarget))
-                    .transpose()
-                    .and_then(|target| target.ok_or(Error::ParseEntryTargetMissing))
-                    .map(|target| (source, target))
-            })
-        })
+        match self.format {
+            Format::Legacy => self.next_legacy(),
+            Format::Versioned => self.next_versioned(),
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum Error {
+    EntryDeployUnknown(String),
+    EntryIdUnknown {
+        key: &'static str,
+        value: String,
+    },
+    EntryIncluded(PathBuf),
+    EntryModeInvalid {
+        key: &'static str,
+        value: String,
+    },
+    EntryNotRegistered(PathBuf),
+    EntryPathNotUtf8(PathBuf),
     EntrySourceNotExists(PathBuf),
-    EntryTargetDuplicate { source: String, target: String },
+    EntryTargetDuplicate {
+        source: PathBuf,
+        target: PathBuf,
+        previous: Location,
+    },
+    EntryTargetEmpty(PathBuf),
+    EntryTargetEscapesRoot(PathBuf),
+    EntryTargetEquivalent {
+        target: PathBuf,
+        previous: Location,
+    },
     EntryTargetExists(PathBuf),
+    EntryTargetOverlap {
+        target: PathBuf,
+        other: PathBuf,
+        previous: Location,
+    },
+    ImportItem {
+        err: Box<Error>,
+        index: usize,
+    },
+    ImportItemField(&'static str),
+    ImportItemNotObject,
+    ImportNotArray,
+    ImportParse(json::Error),
+    ImportRead(io::Error),
+    IncludeCycle(PathBuf),
+    InstallWrite(io::Error),
+    LockConfig(io::Error),
+    Located {
+        err: Box<Error>,
+        location: Location,
+    },
     NewEntrySourceNotAbsolute(PathBuf),
     NewEntryTargetIsAbsolute(PathBuf),
+    NewEntryTargetRegistered(PathBuf),
     OpenConfig(io::Error),
     ParseEntries(Vec<Error>),
+    ParseEntry(io::Error),
+    ParseEntryAttributeDuplicate(String),
+    ParseEntryAttributeInvalid(String),
     ParseEntrySource(io::Error),
+    ParseEntrySourceMissing,
     ParseEntryTarget(io::Error),
     ParseEntryTargetMissing,
+    ParseEntryUnterminated,
+    ParseHeader(io::Error),
+    ParseTrailerCount {
+        expected: usize,
+        actual: usize,
+    },
+    ParseTrailerInvalid(String),
+    ParseTrailerMissing,
+    ParseTrailerNotLast,
     TargetRootNotAbsolute(PathBuf),
     TargetRootNotADirectory(PathBuf),
+    UnsupportedFormat(PathBuf),
+    UnsupportedVersion(String),
+    WriteEntries(io::Error),
     WriteNewEntry(io::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Self::EntryDeployUnknown(value) => write!(out, "entry: unknown deploy mode: {value}"),
+            Self::EntryIdUnknown { key, value } => write!(out, "entry: unknown {key}: {value}"),
+            Self::EntryIncluded(path) => {
+                write!(out, "entry: target registered in an included file: {}", path.display())
+            }
+            Self::EntryModeInvalid { key, value } => write!(out, "entry: invalid {key}: {value}"),
+            Self::EntryNotRegistered(path) => write!(out, "entry: target not registered: {}", path.display()),
+            Self::EntryPathNotUtf8(path) => write!(out, "entry: path is not valid UTF-8: {}", path.display()),
             Self::EntrySourceNotExists(path) => write!(out, "entry: source not exists: {}", path.display()),
-            Self::EntryTargetDuplicate { source, target } => {
-                write!(out, "entry: target duplicate: {source} -> {target}",)
+            Self::EntryTargetDuplicate {
+                source,
+                target,
+                previous,
+            } => write!(
+                out,
+                "entry: target duplicate: {} -> {}, previous entry: {previous}",
+                source.display(),
+                target.display()
+            ),
+            Self::EntryTargetEmpty(path) => write!(out, "entry: target is empty: {:?}", path),
+            Self::EntryTargetEscapesRoot(path) => {
+                write!(out, "entry: target escapes the target root: {}", path.display())
             }
+            Self::EntryTargetEquivalent { target, previous } => write!(
+                out,
+                "entry: target is equivalent to a previous one: {}, previous entry: {previous}",
+                target.display()
+            ),
             Self::EntryTargetExists(path) => write!(out, "entry: target already exists: {}", path.display()),
+            Self::EntryTargetOverlap {
+                target,
+                other,
+                previous,
+            } => write!(
+                out,
+                "entry: target overlaps with {}: {}, previous entry: {previous}",
+                other.display(),
+                target.display()
+            ),
+            Self::ImportItem { err, index } => write!(out, "item {index}: {err}"),
+            Self::ImportItemField(name) => write!(out, "field is missing or is not a string: {name}"),
+            Self::ImportItemNotObject => write!(out, "not an object"),
+            Self::ImportNotArray => write!(out, "json is not an array"),
+            Self::ImportParse(err) => write!(out, "parse json: {err}"),
+            Self::ImportRead(err) => write!(out, "read json: {err}"),
+            Self::IncludeCycle(path) => write!(out, "include: cycle: {}", path.display()),
+            Self::InstallWrite(err) => write!(out, "install: write target: {err}"),
+            Self::LockConfig(err) => write!(out, "lock config: {err}"),
+            Self::Located { err, location } => write!(out, "{location}: {err}"),
             Self::NewEntrySourceNotAbsolute(path) => {
                 write!(out, "new entry: source is not an absolute path: {}", path.display())
             }
             Self::NewEntryTargetIsAbsolute(path) => {
                 write!(out, "new entry: target must be a relative path: {}", path.display())
             }
+            Self::NewEntryTargetRegistered(path) => {
+                write!(out, "new entry: target already registered: {}", path.display())
+            }
             Self::OpenConfig(err) => write!(out, "open config: {err}"),
             Self::ParseEntries(errors) => {
                 let msg = errors
@@ -180,11 +1074,28 @@ impl fmt::Display for Error {
                     .fold(String::from("parse entries:"), |acc, x| format!("{acc}\n\t{x}"));
                 write!(out, "{msg}")
             }
+            Self::ParseEntry(err) => write!(out, "parse entry: {err}"),
+            Self::ParseEntryAttributeDuplicate(key) => write!(out, "parse entry attribute: duplicate: {key}"),
+            Self::ParseEntryAttributeInvalid(line) => write!(out, "parse entry attribute: invalid: {line}"),
             Self::ParseEntrySource(err) => write!(out, "parse entry source: {err}"),
+            Self::ParseEntrySourceMissing => write!(out, "parse entry source: missing"),
             Self::ParseEntryTarget(err) => write!(out, "parse entry target: {err}"),
             Self::ParseEntryTargetMissing => write!(out, "parse entry target: missing"),
+            Self::ParseEntryUnterminated => write!(out, "parse entry: unterminated"),
+            Self::ParseHeader(err) => write!(out, "parse header: {err}"),
+            Self::ParseTrailerCount { expected, actual } => {
+                write!(out, "parse trailer: expected {expected} records, found {actual}")
+            }
+            Self::ParseTrailerInvalid(count) => write!(out, "parse trailer: invalid count: {count}"),
+            Self::ParseTrailerMissing => write!(out, "parse trailer: missing, the file is truncated"),
+            Self::ParseTrailerNotLast => write!(out, "parse trailer: not the last line"),
             Self::TargetRootNotAbsolute(path) => write!(out, "target root is not an absolute path: {}", path.display()),
             Self::TargetRootNotADirectory(path) => write!(out, "target root is not a directory: {}", path.display()),
+            Self::UnsupportedFormat(path) => {
+                write!(out, "unsupported format, run migrate first: {}", path.display())
+            }
+            Self::UnsupportedVersion(version) => write!(out, "unsupported version: {version}"),
+            Self::WriteEntries(err) => write!(out, "write entries: {err}"),
             Self::WriteNewEntry(err) => write!(out, "write new entry: {err}"),
         }
     }
@@ -193,17 +1104,47 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         Some(match self {
-            Self::EntrySourceNotExists(_)
+            Self::EntryDeployUnknown(_)
+            | Self::EntryIdUnknown { .. }
+            | Self::EntryIncluded(_)
+            | Self::EntryModeInvalid { .. }
+            | Self::EntryNotRegistered(_)
+            | Self::EntryPathNotUtf8(_)
+            | Self::EntrySourceNotExists(_)
             | Self::EntryTargetDuplicate { .. }
+            | Self::EntryTargetEmpty(_)
+            | Self::EntryTargetEscapesRoot(_)
+            | Self::EntryTargetEquivalent { .. }
             | Self::EntryTargetExists(_)
+            | Self::EntryTargetOverlap { .. }
+            | Self::ImportItemField(_)
+            | Self::ImportItemNotObject
+            | Self::ImportNotArray
+            | Self::IncludeCycle(_)
             | Self::NewEntrySourceNotAbsolute(_)
-            | Self::NewEntryTargetIsAbsolute(_) => return None,
+            | Self::NewEntryTargetIsAbsolute(_)
+            | Self::NewEntryTargetRegistered(_) => return None,
+            Self::ImportItem { err, .. } => err.as_ref(),
+            Self::ImportParse(err) => err,
+            Self::ImportRead(err) => err,
+            Self::InstallWrite(err) | Self::LockConfig(err) => err,
+            Self::Located { err, .. } => err.as_ref(),
             Self::OpenConfig(err) => err,
-            Self::ParseEntries(_) => return None,
-            Self::ParseEntrySource(err) | Self::ParseEntryTarget(err) => err,
-            Self::ParseEntryTargetMissing => return None,
+            Self::ParseEntries(_) | Self::ParseEntryAttributeDuplicate(_) | Self::ParseEntryAttributeInvalid(_) => {
+                return None
+            }
+            Self::ParseEntry(err) | Self::ParseEntrySource(err) | Self::ParseEntryTarget(err) => err,
+            Self::ParseEntrySourceMissing | Self::ParseEntryTargetMissing | Self::ParseEntryUnterminated => {
+                return None
+            }
+            Self::ParseHeader(err) => err,
+            Self::ParseTrailerCount { .. }
+            | Self::ParseTrailerInvalid(_)
+            | Self::ParseTrailerMissing
+            | Self::ParseTrailerNotLast => return None,
             Self::TargetRootNotAbsolute(_) | Self::TargetRootNotADirectory(_) => return None,
-            Self::WriteNewEntry(err) => err,
+            Self::UnsupportedFormat(_) | Self::UnsupportedVersion(_) => return None,
+            Self::WriteEntries(err) | Self::WriteNewEntry(err) => err,
         })
     }
 }
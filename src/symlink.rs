@@ -1,61 +1,1161 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
     error,
+    ffi::OsString,
     fmt,
-    fs::{canonicalize, create_dir, create_dir_all, remove_file, DirEntry},
-    io,
-    os::unix::fs::symlink,
+    fs::{
+        canonicalize,
+        create_dir,
+        hard_link,
+        metadata,
+        read,
+        read_link,
+        remove_dir,
+        remove_dir_all,
+        remove_file,
+        rename,
+        set_permissions,
+        symlink_metadata,
+        write,
+        DirBuilder,
+        DirEntry,
+        File,
+        Permissions,
+    },
+    io::{self, Write},
+    mem,
+    ops::Bound,
+    os::unix::fs::{lchown, symlink, DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-pub fn create(source: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<(), Error> {
-    let source = source.as_ref();
-    let target = target.as_ref();
-    let state = State::new(source, target)?;
-    match state {
-        State::Equals => Ok(()),
-        State::VacantFile {
-            source_path,
-            target_path,
-            target_exists,
-        } => {
-            if target_exists {
-                remove_symlink(target_path)?;
+use crate::{
+    manifest::Manifest,
+    sha256,
+    template::{self, Variables},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Conflict {
+    #[default]
+    Fail,
+    Backup,
+    Overwrite,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrossDevice {
+    #[default]
+    Fail,
+    Copy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Deploy {
+    #[default]
+    Symlink,
+    Copy,
+    Hardlink,
+    Template,
+}
+
+impl FromStr for Deploy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "symlink" => Self::Symlink,
+            "copy" => Self::Copy,
+            "hardlink" => Self::Hardlink,
+            "template" => Self::Template,
+            _ => return Err(String::from(s)),
+        })
+    }
+}
+
+impl fmt::Display for Deploy {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Symlink => write!(out, "symlink"),
+            Self::Copy => write!(out, "copy"),
+            Self::Hardlink => write!(out, "hardlink"),
+            Self::Template => write!(out, "template"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Access {
+    pub mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+impl Access {
+    pub fn effective(self, euid: u32) -> Self {
+        if euid == 0 {
+            self
+        } else {
+            Self {
+                owner: None,
+                group: None,
+                ..self
+            }
+        }
+    }
+
+    fn file_attributes(&self, deploy: Deploy) -> Attributes {
+        let access = self.effective(effective_uid());
+        match deploy {
+            Deploy::Copy | Deploy::Template => Attributes {
+                mode: access.mode,
+                owner: access.owner,
+                group: access.group,
+            },
+            Deploy::Hardlink => Attributes::default(),
+            Deploy::Symlink => Attributes {
+                mode: None,
+                owner: access.owner,
+                group: access.group,
+            },
+        }
+    }
+
+    fn directory_attributes(&self) -> Attributes {
+        let access = self.effective(effective_uid());
+        Attributes {
+            mode: access.dir_mode,
+            owner: access.owner,
+            group: access.group,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+impl Attributes {
+    fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.owner.is_none() && self.group.is_none()
+    }
+
+    fn matches(&self, path: &Path) -> Result<bool, Error> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        let metadata = symlink_metadata(path).map_err(|err| Error::read_metadata(err, path))?;
+        Ok(self.mode.is_none_or(|mode| metadata.mode() & 0o7777 == mode)
+            && self.owner.is_none_or(|owner| metadata.uid() == owner)
+            && self.group.is_none_or(|group| metadata.gid() == group))
+    }
+
+    fn apply(&self, path: &Path) -> Result<(), Error> {
+        if self.owner.is_some() || self.group.is_some() {
+            lchown(path, self.owner, self.group).map_err(|err| Error::set_owner(err, path))?;
+        }
+        if let Some(mode) = self.mode {
+            set_permissions(path, Permissions::from_mode(mode)).map_err(|err| Error::set_mode(err, path))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Attributes {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(mode) = self.mode {
+            parts.push(format!("mode {mode:o}"));
+        }
+        if let Some(owner) = self.owner {
+            parts.push(format!("owner {owner}"));
+        }
+        if let Some(group) = self.group {
+            parts.push(format!("group {group}"));
+        }
+        if !parts.is_empty() {
+            write!(out, " ({})", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+    conflict: Conflict,
+    cross_device: CrossDevice,
+    backups: BTreeMap<PathBuf, PathBuf>,
+    links: BTreeMap<PathBuf, PathBuf>,
+    copies: BTreeMap<PathBuf, String>,
+    hardlinks: BTreeMap<PathBuf, String>,
+    directories: BTreeSet<PathBuf>,
+    variables: Variables,
+    operations: Vec<Operation>,
+    created: HashSet<PathBuf>,
+    removed: HashSet<PathBuf>,
+    restores: Vec<PathBuf>,
+}
+
+impl Plan {
+    pub fn new(conflict: Conflict, cross_device: CrossDevice, manifest: &Manifest) -> Self {
+        Self {
+            conflict,
+            cross_device,
+            backups: manifest.backups().clone(),
+            links: manifest.links().clone(),
+            copies: manifest.copies().clone(),
+            hardlinks: manifest.hardlinks().clone(),
+            directories: manifest.directories().clone(),
+            variables: template::variables(),
+            ..Self::default()
+        }
+    }
+
+    pub fn create(
+        &mut self,
+        source: impl AsRef<Path>,
+        target: impl AsRef<Path>,
+        deploy: Deploy,
+        access: &Access,
+    ) -> Result<(), Error> {
+        let source = source.as_ref();
+        let target = target.as_ref();
+        if source.is_dir() {
+            self.prune_directory_entries(source, source, target)?;
+        }
+        self.create_path(source, source, target, deploy, access)?;
+        self.enforce_directories(target, access)
+    }
+
+    pub fn remove(&mut self, source: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<(), Error> {
+        let source = source.as_ref();
+        let target = target.as_ref();
+        if self.remove_recorded(target)? {
+            self.restore_backup(target);
+            return Ok(());
+        }
+        if !source.exists() && !target.is_symlink() && target.is_dir() {
+            self.remove_directory_entries(source, target)?;
+            self.restore_backup(target);
+            return Ok(());
+        }
+        let state = self
+            .state(source, target, Deploy::Symlink)
+            .and_then(|state| self.check_owned(source, state))?;
+        match state {
+            State::Obsolete { .. } | State::Equals => self.remove_symlink(target),
+            State::VacantFile { occupant, .. } => match occupant {
+                Some(Occupant::Copy) => self.remove_copy(target),
+                Some(Occupant::Hardlink) => self.remove_hardlink(target),
+                Some(Occupant::Symlink) => self.remove_symlink(target),
+                None => self.restore_backup(target),
+            },
+            State::VacantDirectory { .. } => {
+                self.remove_directory_entries(source, target)?;
+                self.restore_backup(target);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) {
+        let directories: Vec<PathBuf> = self.directories.iter().rev().cloned().collect();
+        for directory in directories {
+            let emptied = self
+                .removed
+                .iter()
+                .any(|path| path != &directory && path.starts_with(&directory));
+            if emptied
+                && !self.removed.contains(&directory)
+                && !self.created.contains(&directory)
+                && self.is_vacant(&directory)
+            {
+                self.push(Operation::RemoveDirectory(directory));
+            }
+        }
+        for target in mem::take(&mut self.restores) {
+            if self.created.contains(&target) {
+                continue;
+            }
+            if let Some(backup) = self.backups.get(&target) {
+                self.push(Operation::RestoreBackup {
+                    backup: backup.clone(),
+                    target,
+                });
+            }
+        }
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn apply(&self, mut applied: impl FnMut(&Operation)) -> Result<(), Error> {
+        let mut journal = Vec::new();
+        for operation in &self.operations {
+            if let Err(err) = operation.apply(&mut journal) {
+                return Err(rollback(journal, err));
+            }
+            applied(operation);
+        }
+        for operation in &self.operations {
+            if let Operation::Overwrite(path) = operation {
+                remove_any(&stash_path(path))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_path(
+        &mut self,
+        root: &Path,
+        source: &Path,
+        target: &Path,
+        deploy: Deploy,
+        access: &Access,
+    ) -> Result<(), Error> {
+        let deploy = self.resolve_deploy(source, target, deploy)?;
+        let state = match self
+            .state(source, target, deploy)
+            .and_then(|state| self.check_owned(root, state))
+        {
+            Err(Error::TargetOccupied(_) | Error::TargetForeign(_) | Error::TargetModified(_))
+                if self.conflict != Conflict::Fail =>
+            {
+                self.resolve_conflict(target);
+                self.state(source, target, deploy)?
+            }
+            state => state?,
+        };
+        match state {
+            State::Obsolete { target_path } => {
+                self.push(Operation::RemoveSymlink(target_path.to_owned()));
+                self.create_path(root, source, target, deploy, access)
+            }
+            State::Equals => self.enforce(target, access.file_attributes(deploy)),
+            State::VacantFile {
+                source_path,
+                target_path,
+                occupant,
+            } => self.create_file(source_path, target_path, occupant, deploy, access),
+            State::VacantDirectory {
+                source_path,
+                target_path,
+            } => self.create_directory(root, source_path, target_path, deploy, access),
+        }
+    }
+
+    fn push(&mut self, operation: Operation) {
+        match &operation {
+            Operation::Backup { target: path, .. }
+            | Operation::Overwrite(path)
+            | Operation::RemoveCopy(path)
+            | Operation::RemoveDirectory(path)
+            | Operation::RemoveHardlink(path)
+            | Operation::RemoveSymlink(path) => {
+                self.removed.insert(path.clone());
+            }
+            Operation::SetMode { .. } | Operation::SetOwner { .. } => {}
+            Operation::CopyFile { target: path, .. }
+            | Operation::CreateDirectory { path, .. }
+            | Operation::CreateHardlink { target: path, .. }
+            | Operation::CreateSymlink { target: path, .. }
+            | Operation::RenderTemplate { target: path, .. }
+            | Operation::ReplaceSymlink { target: path, .. }
+            | Operation::RestoreBackup { target: path, .. } => {
+                self.created.insert(path.clone());
+            }
+        }
+        self.operations.push(operation);
+    }
+
+    fn is_vacant(&self, directory: &Path) -> bool {
+        if directory.is_symlink() || !directory.is_dir() {
+            return false;
+        }
+        let is_parent = |path: &PathBuf| path.parent() == Some(directory);
+        if self.created.iter().any(is_parent) || self.restores.iter().any(is_parent) {
+            return false;
+        }
+        read_directory(directory)
+            .is_ok_and(|mut entries| entries.all(|entry| entry.is_ok_and(|entry| self.removed.contains(&entry.path()))))
+    }
+
+    fn check_owned<'a>(&self, root: &Path, state: State<'a>) -> Result<State<'a>, Error> {
+        match state {
+            State::Obsolete { target_path }
+            | State::VacantFile {
+                target_path,
+                occupant: Some(Occupant::Symlink),
+                ..
+            } if !self.is_owned(root, target_path) => Err(Error::target_foreign(target_path)),
+            state => Ok(state),
+        }
+    }
+
+    fn is_owned(&self, root: &Path, target: &Path) -> bool {
+        read_link(target).is_ok_and(|path| path.starts_with(root) || self.links.get(target) == Some(&path))
+            || canonicalize(target).is_ok_and(|path| path.starts_with(root))
+    }
+
+    fn is_removed(&self, path: &Path) -> bool {
+        path.ancestors().any(|ancestor| self.removed.contains(ancestor))
+    }
+
+    fn resolve_conflict(&mut self, target: &Path) {
+        let target = target.to_owned();
+        match self.conflict {
+            Conflict::Fail => {}
+            Conflict::Backup => {
+                let backup = backup_path(&target);
+                self.push(Operation::Backup { target, backup });
+            }
+            Conflict::Overwrite => self.push(Operation::Overwrite(target)),
+        }
+    }
+
+    fn resolve_deploy(&self, source: &Path, target: &Path, deploy: Deploy) -> Result<Deploy, Error> {
+        if deploy != Deploy::Hardlink || source.is_dir() || is_same_device(source, target)? {
+            return Ok(deploy);
+        }
+        match self.cross_device {
+            CrossDevice::Fail => Err(Error::cross_device(source, target)),
+            CrossDevice::Copy => Ok(Deploy::Copy),
+        }
+    }
+
+    fn state<'a>(&self, source: &'a Path, target: &'a Path, deploy: Deploy) -> Result<State<'a>, Error> {
+        let target_state = if self.is_removed(target) {
+            TargetState::NotPresent
+        } else {
+            let variables = (deploy == Deploy::Template).then_some(&self.variables);
+            TargetState::new(source, target, &self.copies, &self.hardlinks, variables)?
+        };
+        State::new(source, target, target_state, deploy)
+    }
+
+    fn create_directory(
+        &mut self,
+        root: &Path,
+        source: &Path,
+        target: &Path,
+        deploy: Deploy,
+        access: &Access,
+    ) -> Result<(), Error> {
+        self.create_directory_all(target, access.directory_attributes());
+        let source_entries = read_directory(source)?;
+        for source_entry in source_entries {
+            let source_entry = source_entry?;
+            let source_entry_path = source_entry.path();
+            let file_name = source_entry.file_name();
+            let target_entry_path = target.join(file_name);
+            self.create_path(root, &source_entry_path, &target_entry_path, deploy, access)?;
+        }
+        Ok(())
+    }
+
+    fn create_directory_all(&mut self, path: &Path, attributes: Attributes) {
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|ancestor| {
+                (!ancestor.exists() || self.is_removed(ancestor)) && !self.created.contains(*ancestor)
+            })
+            .collect();
+        for ancestor in missing.into_iter().rev() {
+            self.push(Operation::CreateDirectory {
+                path: ancestor.to_owned(),
+                attributes,
+            });
+        }
+    }
+
+    fn create_file(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        occupant: Option<Occupant>,
+        deploy: Deploy,
+        access: &Access,
+    ) -> Result<(), Error> {
+        let attributes = access.file_attributes(deploy);
+        let source = source.to_owned();
+        let target = target.to_owned();
+        match occupant {
+            Some(Occupant::Symlink) if deploy == Deploy::Symlink => {
+                self.push(Operation::ReplaceSymlink {
+                    source,
+                    target: target.clone(),
+                });
+                return self.enforce(&target, attributes);
+            }
+            Some(Occupant::Symlink) => self.push(Operation::RemoveSymlink(target.clone())),
+            Some(Occupant::Copy) if matches!(deploy, Deploy::Copy | Deploy::Template) => {}
+            Some(Occupant::Copy) => self.push(Operation::RemoveCopy(target.clone())),
+            Some(Occupant::Hardlink) => self.push(Operation::RemoveHardlink(target.clone())),
+            None => {
+                if let Some(parent) = target.parent() {
+                    self.create_directory_all(parent, access.directory_attributes());
+                }
+            }
+        }
+        match deploy {
+            Deploy::Symlink => {
+                self.create_symlink(source, target.clone());
+                self.enforce(&target, attributes)?;
+            }
+            Deploy::Copy => {
+                let hash = hash_file(&source)?;
+                self.push(Operation::CopyFile {
+                    source,
+                    target,
+                    hash,
+                    attributes,
+                });
+            }
+            Deploy::Hardlink => {
+                let id = file_id(&source)?;
+                self.push(Operation::CreateHardlink { source, target, id });
+            }
+            Deploy::Template => {
+                let data = render_file(&source, &self.variables)?;
+                let hash = sha256::digest(&data);
+                self.push(Operation::RenderTemplate {
+                    source,
+                    target,
+                    data,
+                    hash,
+                    attributes,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn enforce(&mut self, path: &Path, attributes: Attributes) -> Result<(), Error> {
+        if attributes.is_empty() {
+            return Ok(());
+        }
+        let current = if self.created.contains(path) {
+            None
+        } else {
+            Some(symlink_metadata(path).map_err(|err| Error::read_metadata(err, path))?)
+        };
+        let mode = attributes.mode.filter(|mode| {
+            current
+                .as_ref()
+                .is_none_or(|metadata| metadata.mode() & 0o7777 != *mode)
+        });
+        let owner = attributes
+            .owner
+            .filter(|owner| current.as_ref().is_none_or(|metadata| metadata.uid() != *owner));
+        let group = attributes
+            .group
+            .filter(|group| current.as_ref().is_none_or(|metadata| metadata.gid() != *group));
+        let path = path.to_owned();
+        if owner.is_some() || group.is_some() {
+            self.push(Operation::SetOwner {
+                path: path.clone(),
+                owner,
+                group,
+            });
+        }
+        if let Some(mode) = mode {
+            self.push(Operation::SetMode { path, mode });
+        }
+        Ok(())
+    }
+
+    fn enforce_directories(&mut self, target: &Path, access: &Access) -> Result<(), Error> {
+        let mut owned = self.directories.clone();
+        owned.retain(|path| !self.is_removed(path));
+        let created: BTreeMap<PathBuf, Attributes> = self
+            .operations
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::CreateDirectory { path, attributes } => Some((path.clone(), *attributes)),
+                _ => None,
+            })
+            .collect();
+        owned.extend(created.keys().cloned());
+        let paths: Vec<PathBuf> = target
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| owned.contains(*ancestor))
+            .map(Path::to_owned)
+            .chain(nested(&owned, target).cloned())
+            .collect();
+        let attributes = access.directory_attributes();
+        for path in paths {
+            if created.get(&path) != Some(&attributes) {
+                self.enforce(&path, attributes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_copy(&mut self, target: &Path) {
+        self.push(Operation::RemoveCopy(target.to_owned()));
+        self.restore_backup(target);
+    }
+
+    fn remove_hardlink(&mut self, target: &Path) {
+        self.push(Operation::RemoveHardlink(target.to_owned()));
+        self.restore_backup(target);
+    }
+
+    fn create_symlink(&mut self, source: PathBuf, target: PathBuf) {
+        let removed = self
+            .operations
+            .iter()
+            .position(|operation| *operation == Operation::RemoveSymlink(target.clone()));
+        match removed {
+            Some(index) if !self.restores.contains(&target) => {
+                self.removed.remove(&target);
+                self.created.insert(target.clone());
+                self.operations[index] = Operation::ReplaceSymlink { source, target };
+            }
+            _ => self.push(Operation::CreateSymlink { source, target }),
+        }
+    }
+
+    fn remove_symlink(&mut self, target: &Path) {
+        self.push(Operation::RemoveSymlink(target.to_owned()));
+        self.restore_backup(target);
+    }
+
+    fn restore_backup(&mut self, target: &Path) {
+        if self.backups.contains_key(target) {
+            self.restores.push(target.to_owned());
+        }
+    }
+
+    fn remove_recorded(&mut self, target: &Path) -> Result<bool, Error> {
+        let links: Vec<(PathBuf, PathBuf)> = recorded(&self.links, target)
+            .map(|(path, source)| (path.clone(), source.clone()))
+            .collect();
+        let copies: Vec<(PathBuf, String)> = recorded(&self.copies, target)
+            .map(|(path, hash)| (path.clone(), hash.clone()))
+            .collect();
+        let hardlinks: Vec<(PathBuf, String)> = recorded(&self.hardlinks, target)
+            .map(|(path, id)| (path.clone(), id.clone()))
+            .collect();
+        if links.is_empty() && copies.is_empty() && hardlinks.is_empty() {
+            return Ok(false);
+        }
+        for (path, source) in links {
+            if !self.removed.contains(&path) && read_link(&path).is_ok_and(|link| link == source) {
+                self.remove_symlink(&path);
+            }
+        }
+        for (path, hash) in copies {
+            if self.removed.contains(&path) || path.is_symlink() || !path.is_file() {
+                continue;
+            }
+            if hash_file(&path)? != hash {
+                return Err(Error::target_modified(path));
+            }
+            self.remove_copy(&path);
+        }
+        for (path, id) in hardlinks {
+            if self.removed.contains(&path) || path.is_symlink() || !path.is_file() {
+                continue;
+            }
+            if file_id(&path)? != id {
+                return Err(Error::target_modified(path));
+            }
+            self.remove_hardlink(&path);
+        }
+        Ok(true)
+    }
+
+    fn prune_directory_entries(&mut self, root: &Path, source: &Path, target: &Path) -> Result<(), Error> {
+        if target.is_symlink() || !target.is_dir() {
+            return Ok(());
+        }
+        for target_entry in read_directory(target)? {
+            let target_entry_path = target_entry?.path();
+            let Ok(relative_target_path) = target_entry_path.strip_prefix(target) else {
+                continue;
+            };
+            let source_entry_path = source.join(relative_target_path);
+            if target_entry_path.is_symlink() {
+                if !source_entry_path.exists()
+                    && !self.removed.contains(&target_entry_path)
+                    && self.is_owned(root, &target_entry_path)
+                {
+                    self.remove_symlink(&target_entry_path);
+                }
+            } else if let Some(hash) = self.copies.get(&target_entry_path) {
+                if !source_entry_path.exists()
+                    && !self.removed.contains(&target_entry_path)
+                    && hash_file(&target_entry_path)? == *hash
+                {
+                    self.remove_copy(&target_entry_path);
+                }
+            } else if let Some(id) = self.hardlinks.get(&target_entry_path) {
+                if !source_entry_path.exists()
+                    && !self.removed.contains(&target_entry_path)
+                    && file_id(&target_entry_path)? == *id
+                {
+                    self.remove_hardlink(&target_entry_path);
+                }
+            } else if source_entry_path.is_dir() || !source_entry_path.exists() {
+                self.prune_directory_entries(root, &source_entry_path, &target_entry_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_directory_entries(&mut self, source: &Path, target: &Path) -> Result<(), Error> {
+        if !target.exists() {
+            return Ok(());
+        }
+        let target_entries = read_directory(target)?;
+        for target_entry in target_entries {
+            let target_entry = target_entry?;
+            let target_entry_path = target_entry.path();
+            if target_entry_path.is_symlink() {
+                if self.is_owned(source, &target_entry_path) {
+                    self.remove_symlink(&target_entry_path);
+                }
+            } else if target_entry_path.is_dir() {
+                if let Ok(relative_target_path) = target_entry_path.strip_prefix(target) {
+                    let source_entry_path = source.join(relative_target_path);
+                    if source_entry_path.is_dir() || !source_entry_path.exists() {
+                        self.remove_directory_entries(&source_entry_path, &target_entry_path)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Operation {
+    Backup {
+        target: PathBuf,
+        backup: PathBuf,
+    },
+    CopyFile {
+        source: PathBuf,
+        target: PathBuf,
+        hash: String,
+        attributes: Attributes,
+    },
+    CreateDirectory {
+        path: PathBuf,
+        attributes: Attributes,
+    },
+    CreateHardlink {
+        source: PathBuf,
+        target: PathBuf,
+        id: String,
+    },
+    CreateSymlink {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Overwrite(PathBuf),
+    ReplaceSymlink {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    RemoveCopy(PathBuf),
+    RemoveDirectory(PathBuf),
+    RemoveHardlink(PathBuf),
+    RemoveSymlink(PathBuf),
+    RenderTemplate {
+        source: PathBuf,
+        target: PathBuf,
+        data: Vec<u8>,
+        hash: String,
+        attributes: Attributes,
+    },
+    RestoreBackup {
+        backup: PathBuf,
+        target: PathBuf,
+    },
+    SetMode {
+        path: PathBuf,
+        mode: u32,
+    },
+    SetOwner {
+        path: PathBuf,
+        owner: Option<u32>,
+        group: Option<u32>,
+    },
+}
+
+impl Operation {
+    fn apply(&self, journal: &mut Vec<Undo>) -> Result<(), Error> {
+        match self {
+            Self::Backup { target, backup } => {
+                rename_path(target, backup)?;
+                journal.push(Undo::Rename {
+                    from: backup.clone(),
+                    to: target.clone(),
+                });
+            }
+            Self::CopyFile {
+                source,
+                target,
+                attributes,
+                ..
+            } => {
+                let undo = replaced_file(target)?;
+                copy_file(source, target, *attributes)?;
+                journal.push(undo);
+            }
+            Self::CreateDirectory { path, attributes } => {
+                DirBuilder::new()
+                    .mode(attributes.mode.unwrap_or(0o777))
+                    .create(path)
+                    .map_err(|err| Error::create_target_directory(err, path))?;
+                journal.push(Undo::RemoveDirectory(path.clone()));
+                attributes.apply(path)?;
+            }
+            Self::CreateHardlink { source, target, .. } => {
+                hard_link(source, target).map_err(|err| Error::create_hardlink(err, source, target))?;
+                journal.push(Undo::RemoveFile(target.clone()));
+            }
+            Self::CreateSymlink { source, target } => {
+                create_symlink(source, target)?;
+                journal.push(Undo::RemoveSymlink(target.clone()));
+            }
+            Self::Overwrite(path) => {
+                let stash = stash_path(path);
+                rename_path(path, &stash)?;
+                journal.push(Undo::Rename {
+                    from: stash,
+                    to: path.clone(),
+                });
+            }
+            Self::ReplaceSymlink { source, target } => {
+                let previous_source = read_symlink(target)?;
+                let temporary = temporary_path(target);
+                create_symlink(source, &temporary)?;
+                if let Err(err) = rename_path(&temporary, target) {
+                    let _ = remove_file(&temporary);
+                    return Err(err);
+                }
+                journal.push(Undo::RestoreSymlink {
+                    source: previous_source,
+                    target: target.clone(),
+                });
+                journal.push(Undo::RemoveSymlink(target.clone()));
+            }
+            Self::RemoveCopy(path) | Self::RemoveHardlink(path) => {
+                let data = read_file(path)?;
+                let permissions = symlink_metadata(path)
+                    .map_err(|err| Error::read_file(err, path))?
+                    .permissions();
+                remove_symlink(path)?;
+                journal.push(Undo::RestoreFile {
+                    path: path.clone(),
+                    data,
+                    permissions,
+                });
+            }
+            Self::RemoveDirectory(path) => {
+                remove_dir(path).map_err(|err| Error::remove_directory(err, path))?;
+                journal.push(Undo::CreateDirectory(path.clone()));
+            }
+            Self::RemoveSymlink(path) => {
+                let previous_source = read_symlink(path)?;
+                remove_symlink(path)?;
+                journal.push(Undo::RestoreSymlink {
+                    source: previous_source,
+                    target: path.clone(),
+                });
+            }
+            Self::RenderTemplate {
+                source,
+                target,
+                data,
+                attributes,
+                ..
+            } => {
+                let undo = replaced_file(target)?;
+                write_rendered(source, target, data, *attributes)?;
+                journal.push(undo);
+            }
+            Self::RestoreBackup { backup, target } => {
+                rename_path(backup, target)?;
+                journal.push(Undo::Rename {
+                    from: target.clone(),
+                    to: backup.clone(),
+                });
+            }
+            Self::SetMode { path, mode } => {
+                let permissions = symlink_metadata(path)
+                    .map_err(|err| Error::read_metadata(err, path))?
+                    .permissions();
+                set_permissions(path, Permissions::from_mode(*mode)).map_err(|err| Error::set_mode(err, path))?;
+                journal.push(Undo::SetMode {
+                    path: path.clone(),
+                    permissions,
+                });
+            }
+            Self::SetOwner { path, owner, group } => {
+                let metadata = symlink_metadata(path).map_err(|err| Error::read_metadata(err, path))?;
+                lchown(path, *owner, *group).map_err(|err| Error::set_owner(err, path))?;
+                journal.push(Undo::SetOwner {
+                    path: path.clone(),
+                    owner: metadata.uid(),
+                    group: metadata.gid(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Undo {
+    CreateDirectory(PathBuf),
+    RemoveDirectory(PathBuf),
+    RemoveFile(PathBuf),
+    RemoveSymlink(PathBuf),
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RestoreFile {
+        path: PathBuf,
+        data: Vec<u8>,
+        permissions: Permissions,
+    },
+    RestoreSymlink {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    SetMode {
+        path: PathBuf,
+        permissions: Permissions,
+    },
+    SetOwner {
+        path: PathBuf,
+        owner: u32,
+        group: u32,
+    },
+}
+
+impl Undo {
+    fn apply(self) -> Result<(), Error> {
+        match self {
+            Self::CreateDirectory(path) => create_dir(&path).map_err(|err| Error::create_target_directory(err, path)),
+            Self::RemoveDirectory(path) => remove_dir(&path).map_err(|err| Error::remove_directory(err, path)),
+            Self::RemoveFile(path) | Self::RemoveSymlink(path) => remove_symlink(&path),
+            Self::Rename { from, to } => rename_path(&from, &to),
+            Self::RestoreFile {
+                path,
+                data,
+                permissions,
+            } => write(&path, data)
+                .and_then(|_| set_permissions(&path, permissions))
+                .map_err(|err| Error::write_file(err, path)),
+            Self::RestoreSymlink { source, target } => create_symlink(&source, &target),
+            Self::SetMode { path, permissions } => {
+                set_permissions(&path, permissions).map_err(|err| Error::set_mode(err, path))
+            }
+            Self::SetOwner { path, owner, group } => {
+                lchown(&path, Some(owner), Some(group)).map_err(|err| Error::set_owner(err, path))
+            }
+        }
+    }
+}
+
+fn rollback(journal: Vec<Undo>, err: Error) -> Error {
+    let errors: Vec<Error> = journal
+        .into_iter()
+        .rev()
+        .filter_map(|undo| undo.apply().err())
+        .collect();
+    if errors.is_empty() {
+        err
+    } else {
+        Error::Rollback {
+            err: Box::new(err),
+            errors,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Backup { target, backup } => write!(out, "backup {} -> {}", target.display(), backup.display()),
+            Self::CopyFile {
+                source,
+                target,
+                attributes,
+                ..
+            } => write!(out, "copy {} -> {}{attributes}", source.display(), target.display()),
+            Self::CreateDirectory { path, attributes } => write!(out, "mkdir {}{attributes}", path.display()),
+            Self::CreateHardlink { source, target, .. } => {
+                write!(out, "hardlink {} -> {}", source.display(), target.display())
+            }
+            Self::CreateSymlink { source, target } => {
+                write!(out, "symlink {} -> {}", source.display(), target.display())
+            }
+            Self::Overwrite(path) => write!(out, "overwrite {}", path.display()),
+            Self::ReplaceSymlink { source, target } => {
+                write!(out, "replace symlink {} -> {}", source.display(), target.display())
+            }
+            Self::RemoveCopy(path) => write!(out, "remove copy {}", path.display()),
+            Self::RemoveDirectory(path) => write!(out, "rmdir {}", path.display()),
+            Self::RemoveHardlink(path) => write!(out, "remove hardlink {}", path.display()),
+            Self::RemoveSymlink(path) => write!(out, "remove symlink {}", path.display()),
+            Self::RenderTemplate {
+                source,
+                target,
+                attributes,
+                ..
+            } => write!(out, "render {} -> {}{attributes}", source.display(), target.display()),
+            Self::RestoreBackup { backup, target } => {
+                write!(out, "restore backup {} -> {}", backup.display(), target.display())
+            }
+            Self::SetMode { path, mode } => write!(out, "chmod {mode:o} {}", path.display()),
+            Self::SetOwner { path, owner, group } => {
+                let owner = owner.map(|owner| owner.to_string()).unwrap_or_default();
+                let group = group.map(|group| group.to_string()).unwrap_or_default();
+                write!(out, "chown {owner}:{group} {}", path.display())
             }
-            create_file(source_path, target_path)
         }
-        State::VacantDirectory {
-            source_path,
-            target_path,
-        } => create_directory(source_path, target_path),
     }
 }
 
-pub fn remove(source: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<(), Error> {
+pub fn status(
+    source: impl AsRef<Path>,
+    target: impl AsRef<Path>,
+    deploy: Deploy,
+    access: &Access,
+    manifest: &Manifest,
+) -> Result<Status, Error> {
     let source = source.as_ref();
     let target = target.as_ref();
-    let state = State::new(source, target)?;
-    match state {
-        State::Equals => {
-            remove_symlink(target)?;
-        }
-        State::VacantFile { target_exists, .. } => {
-            if target_exists {
-                remove_symlink(target)?;
+    let variables = template::variables();
+    let status = path_status(source, target, deploy, access, manifest, &variables)?;
+    let has_stale_links = recorded(manifest.links(), target).any(|(path, recorded)| {
+        read_link(path).is_ok_and(|link| link == *recorded) && !(recorded.starts_with(source) && recorded.exists())
+    });
+    if status.is_deployed() && has_stale_links {
+        return Ok(Status::Partial);
+    }
+    if status.is_deployed() {
+        let directories = manifest.directories();
+        let owned = target
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| directories.contains(*ancestor))
+            .chain(nested(directories, target).map(PathBuf::as_path));
+        for path in owned {
+            if !access.directory_attributes().matches(path)? {
+                return Ok(Status::PermissionsDiffer);
             }
         }
-        State::VacantDirectory { .. } => {
-            remove_directory_entries(source, target)?;
+    }
+    Ok(status)
+}
+
+fn path_status(
+    source: &Path,
+    target: &Path,
+    deploy: Deploy,
+    access: &Access,
+    manifest: &Manifest,
+    variables: &Variables,
+) -> Result<Status, Error> {
+    let path_type_source = PathType::from(source);
+    let target_state = TargetState::new(
+        source,
+        target,
+        manifest.copies(),
+        manifest.hardlinks(),
+        (deploy == Deploy::Template).then_some(variables),
+    )?;
+    let status = match (path_type_source, target_state) {
+        (_, TargetState::Modified) => Status::Modified,
+        (PathType::File, TargetState::Copied { current: true }) if deploy == Deploy::Template => Status::Rendered,
+        (PathType::File, TargetState::Copied { current: true }) if deploy != Deploy::Symlink => Status::Copied,
+        (PathType::File, TargetState::Hardlinked { current: true }) if deploy == Deploy::Hardlink => Status::Hardlinked,
+        (_, TargetState::Copied { .. } | TargetState::Hardlinked { .. }) => Status::Outdated,
+        (_, TargetState::Equals) if deploy != Deploy::Symlink => Status::Outdated,
+        (_, TargetState::Equals) => Status::Linked,
+        (_, TargetState::NotPresent) => Status::Missing,
+        (_, TargetState::Dangling) => Status::Dangling,
+        (_, TargetState::PointsTo(_)) => Status::PointsElsewhere,
+        (PathType::Directory, TargetState::Occupied(PathType::Directory)) => {
+            return directory_status(source, target, deploy, access, manifest, variables);
+        }
+        (_, TargetState::Occupied(_)) => Status::Occupied,
+    };
+    let attributes = match status {
+        Status::Copied | Status::Rendered => access.file_attributes(Deploy::Copy),
+        Status::Hardlinked => access.file_attributes(Deploy::Hardlink),
+        _ => access.file_attributes(Deploy::Symlink),
+    };
+    Ok(if status.is_deployed() && !attributes.matches(target)? {
+        Status::PermissionsDiffer
+    } else {
+        status
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Linked,
+    Copied,
+    Hardlinked,
+    Rendered,
+    Missing,
+    Occupied,
+    PointsElsewhere,
+    Dangling,
+    Partial,
+    Modified,
+    Outdated,
+    PermissionsDiffer,
+}
+
+impl Status {
+    pub fn is_deployed(self) -> bool {
+        matches!(self, Self::Linked | Self::Copied | Self::Hardlinked | Self::Rendered)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Linked => write!(out, "linked"),
+            Self::Copied => write!(out, "copied"),
+            Self::Hardlinked => write!(out, "hardlinked"),
+            Self::Rendered => write!(out, "rendered"),
+            Self::Missing => write!(out, "missing"),
+            Self::Occupied => write!(out, "occupied"),
+            Self::PointsElsewhere => write!(out, "points elsewhere"),
+            Self::Dangling => write!(out, "dangling"),
+            Self::Partial => write!(out, "partially linked"),
+            Self::Modified => write!(out, "modified"),
+            Self::Outdated => write!(out, "outdated"),
+            Self::PermissionsDiffer => write!(out, "permissions differ"),
         }
     }
-    Ok(())
 }
 
 enum State<'a> {
+    Obsolete {
+        target_path: &'a Path,
+    },
     Equals,
     VacantFile {
         source_path: &'a Path,
         target_path: &'a Path,
-        target_exists: bool,
+        occupant: Option<Occupant>,
     },
     VacantDirectory {
         source_path: &'a Path,
@@ -63,51 +1163,92 @@ enum State<'a> {
     },
 }
 
+#[derive(Clone, Copy, Debug)]
+enum Occupant {
+    Copy,
+    Hardlink,
+    Symlink,
+}
+
 impl<'a> State<'a> {
-    fn new(source_path: &'a Path, target_path: &'a Path) -> Result<Self, Error> {
+    fn new(
+        source_path: &'a Path,
+        target_path: &'a Path,
+        target_state: TargetState,
+        deploy: Deploy,
+    ) -> Result<Self, Error> {
         let path_type_source = PathType::from(source_path);
-        let target_state = TargetState::new(source_path, target_path)?;
+        let vacant_file = |occupant| {
+            Ok(Self::VacantFile {
+                source_path,
+                target_path,
+                occupant,
+            })
+        };
         match (path_type_source, target_state) {
+            (_, TargetState::Dangling) => Ok(Self::Obsolete { target_path }),
+            (_, TargetState::Modified) => Err(Error::target_modified(target_path)),
+            (PathType::Directory, TargetState::Equals) if deploy != Deploy::Symlink => {
+                Ok(Self::Obsolete { target_path })
+            }
             (PathType::Directory, TargetState::Equals) => Ok(Self::Equals),
             (PathType::Directory, TargetState::NotPresent)
             | (PathType::Directory, TargetState::Occupied(PathType::Directory)) => Ok(Self::VacantDirectory {
                 source_path,
                 target_path,
             }),
-            (PathType::Directory, TargetState::Occupied(PathType::File))
+            (PathType::Directory, TargetState::Copied { .. })
+            | (PathType::Directory, TargetState::Hardlinked { .. })
+            | (PathType::Directory, TargetState::Occupied(PathType::File))
             | (PathType::Directory, TargetState::PointsTo(PathType::File))
             | (PathType::Directory, TargetState::PointsTo(PathType::Directory)) => {
                 Err(Error::target_occupied(target_path))
             }
-            (PathType::File, TargetState::Equals) => Ok(Self::Equals),
-            (PathType::File, TargetState::NotPresent) => Ok(Self::VacantFile {
-                source_path,
-                target_path,
-                target_exists: false,
-            }),
+            (PathType::File, TargetState::Copied { current: true })
+                if deploy == Deploy::Copy || deploy == Deploy::Template =>
+            {
+                Ok(Self::Equals)
+            }
+            (PathType::File, TargetState::Copied { .. }) => vacant_file(Some(Occupant::Copy)),
+            (PathType::File, TargetState::Hardlinked { current: true }) if deploy == Deploy::Hardlink => {
+                Ok(Self::Equals)
+            }
+            (PathType::File, TargetState::Hardlinked { .. }) => vacant_file(Some(Occupant::Hardlink)),
+            (PathType::File, TargetState::Equals) if deploy == Deploy::Symlink => Ok(Self::Equals),
+            (PathType::File, TargetState::NotPresent) => vacant_file(None),
             (PathType::File, TargetState::Occupied(PathType::Directory))
             | (PathType::File, TargetState::Occupied(PathType::File))
             | (PathType::File, TargetState::PointsTo(PathType::Directory)) => Err(Error::target_occupied(target_path)),
-            (PathType::File, TargetState::PointsTo(PathType::File)) => Ok(Self::VacantFile {
-                source_path,
-                target_path,
-                target_exists: true,
-            }),
+            (PathType::File, TargetState::Equals) | (PathType::File, TargetState::PointsTo(PathType::File)) => {
+                vacant_file(Some(Occupant::Symlink))
+            }
         }
     }
 }
 
 #[derive(Clone, Copy, Debug)]
 enum TargetState {
+    Copied { current: bool },
+    Dangling,
     Equals,
+    Hardlinked { current: bool },
+    Modified,
     NotPresent,
     Occupied(PathType),
     PointsTo(PathType),
 }
 
 impl TargetState {
-    fn new(source: &Path, target: &Path) -> Result<Self, Error> {
-        if !target.exists() {
+    fn new(
+        source: &Path,
+        target: &Path,
+        copies: &BTreeMap<PathBuf, String>,
+        hardlinks: &BTreeMap<PathBuf, String>,
+        variables: Option<&Variables>,
+    ) -> Result<Self, Error> {
+        if target.is_symlink() && !target.exists() {
+            Ok(Self::Dangling)
+        } else if !target.exists() {
             Ok(Self::NotPresent)
         } else if target.is_symlink() {
             canonicalize(target)
@@ -119,8 +1260,27 @@ impl TargetState {
                         TargetState::PointsTo(PathType::from(real_target_path.as_ref()))
                     }
                 })
-        } else {
+        } else if !target.is_file() {
             Ok(Self::Occupied(PathType::from(target)))
+        } else if source.is_file() && file_id(source)? == file_id(target)? {
+            Ok(Self::Hardlinked { current: true })
+        } else if let Some(id) = hardlinks.get(target) {
+            Ok(if file_id(target)? == *id {
+                Self::Hardlinked { current: false }
+            } else {
+                Self::Modified
+            })
+        } else if let Some(hash) = copies.get(target) {
+            let target_hash = hash_file(target)?;
+            Ok(if target_hash != *hash {
+                Self::Modified
+            } else {
+                Self::Copied {
+                    current: source.is_file() && source_hash(source, variables)? == target_hash,
+                }
+            })
+        } else {
+            Ok(Self::Occupied(PathType::File))
         }
     }
 }
@@ -141,59 +1301,315 @@ impl From<&Path> for PathType {
     }
 }
 
-fn create_directory(source: &Path, target: &Path) -> Result<(), Error> {
-    if !target.exists() {
-        create_dir(target).map_err(|err| Error::create_target_directory(err, target))?;
-    }
-
+fn directory_status(
+    source: &Path,
+    target: &Path,
+    deploy: Deploy,
+    access: &Access,
+    manifest: &Manifest,
+    variables: &Variables,
+) -> Result<Status, Error> {
+    let mut linked = true;
+    let mut missing = true;
+    let mut differ = false;
     let source_entries = read_directory(source)?;
     for source_entry in source_entries {
         let source_entry = source_entry?;
-        let source_entry_path = source_entry.path();
-        let file_name = source_entry.file_name();
-        let target_entry_path = target.join(file_name);
-        create(source_entry_path, target_entry_path)?;
+        let target_entry_path = target.join(source_entry.file_name());
+        match path_status(
+            &source_entry.path(),
+            &target_entry_path,
+            deploy,
+            access,
+            manifest,
+            variables,
+        )? {
+            status if status.is_deployed() => missing = false,
+            Status::PermissionsDiffer => {
+                missing = false;
+                differ = true;
+            }
+            Status::Missing => linked = false,
+            _ => {
+                linked = false;
+                missing = false;
+            }
+        }
+    }
+    Ok(if linked && differ {
+        Status::PermissionsDiffer
+    } else if linked {
+        match deploy {
+            Deploy::Symlink => Status::Linked,
+            Deploy::Copy => Status::Copied,
+            Deploy::Hardlink => Status::Hardlinked,
+            Deploy::Template => Status::Rendered,
+        }
+    } else if missing {
+        Status::Missing
+    } else {
+        Status::Partial
+    })
+}
+
+pub fn drift(
+    source: impl AsRef<Path>,
+    target: impl AsRef<Path>,
+    deploy: Deploy,
+    manifest: &Manifest,
+) -> Result<Vec<CopiedFile>, Error> {
+    let mut result = Vec::new();
+    if deploy != Deploy::Symlink {
+        path_drift(source.as_ref(), target.as_ref(), deploy, manifest, &mut result)?;
     }
+    Ok(result)
+}
+
+fn path_drift(
+    source: &Path,
+    target: &Path,
+    deploy: Deploy,
+    manifest: &Manifest,
+    result: &mut Vec<CopiedFile>,
+) -> Result<(), Error> {
+    if source.is_dir() {
+        for source_entry in read_directory(source)? {
+            let source_entry = source_entry?;
+            path_drift(
+                &source_entry.path(),
+                &target.join(source_entry.file_name()),
+                deploy,
+                manifest,
+                result,
+            )?;
+        }
+        return Ok(());
+    }
+    if deploy == Deploy::Hardlink && !manifest.copies().contains_key(target) {
+        return Ok(());
+    }
+    let drift = if !target.exists() && !target.is_symlink() {
+        Drift::Missing
+    } else if target.is_symlink() || !target.is_file() {
+        Drift::Untracked
+    } else {
+        match manifest.copies().get(target) {
+            None => Drift::Untracked,
+            Some(hash) if hash_file(target)? != *hash => Drift::Modified,
+            Some(_) => Drift::Clean,
+        }
+    };
+    result.push(CopiedFile {
+        source: source.to_owned(),
+        target: target.to_owned(),
+        deploy,
+        drift,
+    });
     Ok(())
 }
 
-fn create_file(source: &Path, target: &Path) -> Result<(), Error> {
-    if let Some(parent) = target.parent() {
-        if !parent.exists() {
-            create_dir_all(parent).map_err(|err| Error::create_parent(err, target))?;
+#[derive(Debug)]
+pub struct CopiedFile {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub deploy: Deploy,
+    pub drift: Drift,
+}
+
+impl CopiedFile {
+    pub fn expected(&self, variables: &Variables) -> Result<Vec<u8>, Error> {
+        match self.deploy {
+            Deploy::Template => render_file(&self.source, variables),
+            _ => read_file(&self.source),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drift {
+    Clean,
+    Missing,
+    Modified,
+    Untracked,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Clean => write!(out, "clean"),
+            Self::Missing => write!(out, "missing"),
+            Self::Modified => write!(out, "modified"),
+            Self::Untracked => write!(out, "untracked"),
         }
     }
+}
+
+fn recorded<'a, T>(records: &'a BTreeMap<PathBuf, T>, target: &'a Path) -> impl Iterator<Item = (&'a PathBuf, &'a T)> {
+    records
+        .range::<Path, _>((Bound::Included(target), Bound::Unbounded))
+        .take_while(move |(path, _)| path.starts_with(target))
+}
+
+fn nested<'a>(paths: &'a BTreeSet<PathBuf>, target: &'a Path) -> impl Iterator<Item = &'a PathBuf> {
+    paths
+        .range::<Path, _>((Bound::Included(target), Bound::Unbounded))
+        .take_while(move |path| path.starts_with(target))
+}
+
+fn create_symlink(source: &Path, target: &Path) -> Result<(), Error> {
     symlink(source, target).map_err(|err| Error::create_new_symlink(err, source, target))
 }
 
-fn remove_symlink(path: &Path) -> Result<(), Error> {
-    remove_file(path).map_err(|err| Error::unlink(err, path))
+fn replaced_file(path: &Path) -> Result<Undo, Error> {
+    match symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(Undo::RestoreFile {
+            path: path.to_owned(),
+            data: read_file(path)?,
+            permissions: metadata.permissions(),
+        }),
+        _ => Ok(Undo::RemoveFile(path.to_owned())),
+    }
 }
 
-fn remove_directory_entries(source: &Path, target: &Path) -> Result<(), Error> {
-    if !target.exists() {
-        return Ok(());
+fn copy_file(source: &Path, target: &Path, attributes: Attributes) -> Result<(), Error> {
+    let data = read_file(source)?;
+    install_file(source, target, &data, attributes, |err| {
+        Error::copy_file(err, source, target)
+    })
+}
+
+fn write_rendered(source: &Path, target: &Path, data: &[u8], attributes: Attributes) -> Result<(), Error> {
+    install_file(source, target, data, attributes, |err| Error::write_file(err, target))
+}
+
+fn install_file(
+    source: &Path,
+    target: &Path,
+    data: &[u8],
+    attributes: Attributes,
+    map_err: impl FnOnce(io::Error) -> Error,
+) -> Result<(), Error> {
+    let attributes = Attributes {
+        mode: match attributes.mode {
+            Some(mode) => Some(mode),
+            None => Some(
+                metadata(source)
+                    .map_err(|err| Error::read_metadata(err, source))?
+                    .mode()
+                    & 0o7777,
+            ),
+        },
+        ..attributes
+    };
+    let temporary = temporary_path(target);
+    let _ = remove_file(&temporary);
+    let result = File::options()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temporary)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(map_err)
+        .and_then(|_| attributes.apply(&temporary))
+        .and_then(|_| rename_path(&temporary, target));
+    if result.is_err() {
+        let _ = remove_file(&temporary);
     }
-    let target_entries = read_directory(target)?;
-    for target_entry in target_entries {
-        let target_entry = target_entry?;
-        let target_entry_path = target_entry.path();
-        if target_entry_path.is_symlink() {
-            let real_path =
-                canonicalize(&target_entry_path).map_err(|err| Error::canonicalize_target(err, &target_entry_path))?;
-            if real_path.starts_with(source) {
-                remove(real_path, &target_entry_path)?;
-            }
-        } else if target_entry_path.is_dir() {
-            if let Ok(relative_target_path) = target_entry_path.strip_prefix(target) {
-                let source_entry_path = source.join(relative_target_path);
-                if source_entry_path.is_dir() {
-                    remove_directory_entries(&source_entry_path, &target_entry_path)?;
-                }
-            }
-        }
+    result
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(".makky-copy");
+    path.with_file_name(file_name)
+}
+
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+fn effective_uid() -> u32 {
+    unsafe { geteuid() }
+}
+
+fn is_same_device(source: &Path, target: &Path) -> Result<bool, Error> {
+    let source_dev = metadata(source).map_err(|err| Error::read_metadata(err, source))?.dev();
+    let Some(existing) = target.ancestors().find(|ancestor| ancestor.exists()) else {
+        return Ok(true);
+    };
+    let target_dev = metadata(existing)
+        .map_err(|err| Error::read_metadata(err, existing))?
+        .dev();
+    Ok(source_dev == target_dev)
+}
+
+fn file_id(path: &Path) -> Result<String, Error> {
+    let metadata = metadata(path).map_err(|err| Error::read_metadata(err, path))?;
+    Ok(format!("{}:{}", metadata.dev(), metadata.ino()))
+}
+
+fn hash_file(path: &Path) -> Result<String, Error> {
+    read_file(path).map(|data| sha256::digest(&data))
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    read(path).map_err(|err| Error::read_file(err, path))
+}
+
+fn render_file(path: &Path, variables: &Variables) -> Result<Vec<u8>, Error> {
+    template::render(&read_file(path)?, variables).map_err(|names| Error::TemplateVariables {
+        path: path.to_owned(),
+        names,
+    })
+}
+
+fn source_hash(source: &Path, variables: Option<&Variables>) -> Result<String, Error> {
+    match variables {
+        Some(variables) => render_file(source, variables).map(|data| sha256::digest(&data)),
+        None => hash_file(source),
     }
-    Ok(())
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".makky-backup");
+    let mut result = PathBuf::from(&backup);
+    let mut idx = 0;
+    while result.exists() || result.is_symlink() {
+        idx += 1;
+        let mut numbered = backup.clone();
+        numbered.push(format!(".{idx}"));
+        result = PathBuf::from(numbered);
+    }
+    result
+}
+
+fn stash_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(".makky-overwrite");
+    path.with_file_name(file_name)
+}
+
+fn rename_path(from: &Path, to: &Path) -> Result<(), Error> {
+    rename(from, to).map_err(|err| Error::rename(err, from, to))
+}
+
+fn remove_any(path: &Path) -> Result<(), Error> {
+    if path.is_dir() && !path.is_symlink() {
+        remove_dir_all(path).map_err(|err| Error::remove_directory(err, path))
+    } else {
+        remove_symlink(path)
+    }
+}
+
+fn read_symlink(path: &Path) -> Result<PathBuf, Error> {
+    read_link(path).map_err(|err| Error::read_symlink(err, path))
+}
+
+fn remove_symlink(path: &Path) -> Result<(), Error> {
+    remove_file(path).map_err(|err| Error::unlink(err, path))
 }
 
 fn read_directory(path: &Path) -> Result<impl Iterator<Item = Result<DirEntry, Error>>, Error> {
@@ -208,14 +1624,24 @@ pub enum Error {
         err: io::Error,
         path: PathBuf,
     },
-    CreateNewSymlink {
+    CopyFile {
+        err: io::Error,
+        source: PathBuf,
+        target: PathBuf,
+    },
+    CrossDevice {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    CreateHardlink {
         err: io::Error,
         source: PathBuf,
         target: PathBuf,
     },
-    CreateParent {
+    CreateNewSymlink {
         err: io::Error,
-        path: PathBuf,
+        source: PathBuf,
+        target: PathBuf,
     },
     CreateTargetDirectory {
         err: io::Error,
@@ -225,11 +1651,54 @@ pub enum Error {
         err: io::Error,
         path: PathBuf,
     },
+    ReadFile {
+        err: io::Error,
+        path: PathBuf,
+    },
+    ReadMetadata {
+        err: io::Error,
+        path: PathBuf,
+    },
+    ReadSymlink {
+        err: io::Error,
+        path: PathBuf,
+    },
+    RemoveDirectory {
+        err: io::Error,
+        path: PathBuf,
+    },
+    SetMode {
+        err: io::Error,
+        path: PathBuf,
+    },
+    SetOwner {
+        err: io::Error,
+        path: PathBuf,
+    },
+    Rename {
+        err: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Rollback {
+        err: Box<Error>,
+        errors: Vec<Error>,
+    },
+    TargetForeign(PathBuf),
+    TargetModified(PathBuf),
     TargetOccupied(PathBuf),
+    TemplateVariables {
+        path: PathBuf,
+        names: Vec<String>,
+    },
     Unlink {
         err: io::Error,
         path: PathBuf,
     },
+    WriteFile {
+        err: io::Error,
+        path: PathBuf,
+    },
 }
 
 impl Error {
@@ -237,16 +1706,35 @@ impl Error {
         Self::CanonicalizeTarget { err, path: path.into() }
     }
 
-    fn create_new_symlink(err: io::Error, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
-        Self::CreateNewSymlink {
+    fn copy_file(err: io::Error, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        Self::CopyFile {
+            err,
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+
+    fn cross_device(source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        Self::CrossDevice {
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+
+    fn create_hardlink(err: io::Error, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        Self::CreateHardlink {
             err,
             source: source.into(),
             target: target.into(),
         }
     }
 
-    fn create_parent(err: io::Error, path: impl Into<PathBuf>) -> Self {
-        Self::CreateParent { err, path: path.into() }
+    fn create_new_symlink(err: io::Error, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        Self::CreateNewSymlink {
+            err,
+            source: source.into(),
+            target: target.into(),
+        }
     }
 
     fn create_target_directory(err: io::Error, path: impl Into<PathBuf>) -> Self {
@@ -257,6 +1745,46 @@ impl Error {
         Self::ReadDirectory { err, path: path.into() }
     }
 
+    fn read_file(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::ReadFile { err, path: path.into() }
+    }
+
+    fn read_metadata(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::ReadMetadata { err, path: path.into() }
+    }
+
+    fn read_symlink(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::ReadSymlink { err, path: path.into() }
+    }
+
+    fn remove_directory(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::RemoveDirectory { err, path: path.into() }
+    }
+
+    fn rename(err: io::Error, from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> Self {
+        Self::Rename {
+            err,
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    fn set_mode(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::SetMode { err, path: path.into() }
+    }
+
+    fn set_owner(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::SetOwner { err, path: path.into() }
+    }
+
+    fn target_foreign(path: impl Into<PathBuf>) -> Self {
+        Self::TargetForeign(path.into())
+    }
+
+    fn target_modified(path: impl Into<PathBuf>) -> Self {
+        Self::TargetModified(path.into())
+    }
+
     fn target_occupied(path: impl Into<PathBuf>) -> Self {
         Self::TargetOccupied(path.into())
     }
@@ -264,12 +1792,34 @@ impl Error {
     fn unlink(err: io::Error, path: impl Into<PathBuf>) -> Self {
         Self::Unlink { err, path: path.into() }
     }
+
+    fn write_file(err: io::Error, path: impl Into<PathBuf>) -> Self {
+        Self::WriteFile { err, path: path.into() }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::CanonicalizeTarget { err, path } => write!(out, "canonicalize target: {}: {}", path.display(), err),
+            Self::CopyFile { err, source, target } => {
+                write!(out, "copy file: {} -> {}: {}", source.display(), target.display(), err)
+            }
+            Self::CrossDevice { source, target } => write!(
+                out,
+                "source and target are on different devices: {} -> {}",
+                source.display(),
+                target.display()
+            ),
+            Self::CreateHardlink { err, source, target } => {
+                write!(
+                    out,
+                    "create hardlink: {} -> {}: {}",
+                    source.display(),
+                    target.display(),
+                    err
+                )
+            }
             Self::CreateNewSymlink { err, source, target } => write!(
                 out,
                 "create new symlink: {} -> {}: {}",
@@ -277,15 +1827,46 @@ impl fmt::Display for Error {
                 target.display(),
                 err
             ),
-            Self::CreateParent { err, path } => write!(out, "create parent directory for {}: {}", path.display(), err),
             Self::CreateTargetDirectory { err, path } => {
                 write!(out, "create target directory: {}: {}", path.display(), err)
             }
             Self::ReadDirectory { err, path } => {
                 write!(out, "read directory: {}: {}", path.display(), err)
             }
+            Self::ReadFile { err, path } => {
+                write!(out, "read file: {}: {}", path.display(), err)
+            }
+            Self::ReadMetadata { err, path } => {
+                write!(out, "read metadata: {}: {}", path.display(), err)
+            }
+            Self::ReadSymlink { err, path } => {
+                write!(out, "read symlink: {}: {}", path.display(), err)
+            }
+            Self::RemoveDirectory { err, path } => {
+                write!(out, "remove directory: {}: {}", path.display(), err)
+            }
+            Self::Rename { err, from, to } => {
+                write!(out, "rename: {} -> {}: {}", from.display(), to.display(), err)
+            }
+            Self::SetMode { err, path } => write!(out, "set mode: {}: {}", path.display(), err),
+            Self::SetOwner { err, path } => write!(out, "set owner: {}: {}", path.display(), err),
+            Self::Rollback { err, errors } => {
+                let msg = errors
+                    .iter()
+                    .fold(format!("{err}\nrollback:"), |acc, x| format!("{acc}\n\t{x}"));
+                write!(out, "{msg}")
+            }
+            Self::TargetForeign(path) => write!(out, "target is a foreign symlink: {}", path.display()),
+            Self::TargetModified(path) => write!(out, "target was modified after deployment: {}", path.display()),
             Self::TargetOccupied(path) => write!(out, "target occupied: {}", path.display()),
+            Self::TemplateVariables { path, names } => write!(
+                out,
+                "template variables are not defined: {}: {}",
+                path.display(),
+                names.join(", ")
+            ),
             Self::Unlink { err, path } => write!(out, "unlink: {}: {}", path.display(), err),
+            Self::WriteFile { err, path } => write!(out, "write file: {}: {}", path.display(), err),
         }
     }
 }
@@ -294,12 +1875,23 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         Some(match self {
             Self::CanonicalizeTarget { err, .. } => err,
+            Self::CopyFile { err, .. } => err,
+            Self::CrossDevice { .. } => return None,
+            Self::CreateHardlink { err, .. } => err,
             Self::CreateNewSymlink { err, .. } => err,
-            Self::CreateParent { err, .. } => err,
             Self::CreateTargetDirectory { err, .. } => err,
             Self::ReadDirectory { err, .. } => err,
-            Self::TargetOccupied(_) => return None,
+            Self::ReadFile { err, .. } => err,
+            Self::ReadMetadata { err, .. } => err,
+            Self::ReadSymlink { err, .. } => err,
+            Self::RemoveDirectory { err, .. } => err,
+            Self::Rename { err, .. } => err,
+            Self::SetMode { err, .. } | Self::SetOwner { err, .. } => err,
+            Self::Rollback { err, .. } => err,
+            Self::TargetForeign(_) | Self::TargetModified(_) | Self::TargetOccupied(_) => return None,
+            Self::TemplateVariables { .. } => return None,
             Self::Unlink { err, .. } => err,
+            Self::WriteFile { err, .. } => err,
         })
     }
 }
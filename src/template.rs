@@ -0,0 +1,89 @@
+use std::{collections::BTreeMap, env, fs::read_to_string};
+
+const ENVIRONMENT: &[&str] = &[
+    "HOME",
+    "LANG",
+    "LOGNAME",
+    "SHELL",
+    "USER",
+    "XDG_CACHE_HOME",
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+    "XDG_RUNTIME_DIR",
+    "XDG_STATE_HOME",
+];
+
+const XDG_DEFAULTS: &[(&str, &str)] = &[
+    ("XDG_CACHE_HOME", ".cache"),
+    ("XDG_CONFIG_HOME", ".config"),
+    ("XDG_DATA_HOME", ".local/share"),
+    ("XDG_STATE_HOME", ".local/state"),
+];
+
+pub type Variables = BTreeMap<String, String>;
+
+pub fn variables() -> Variables {
+    let mut result: Variables = ENVIRONMENT
+        .iter()
+        .filter_map(|name| env::var(name).ok().map(|value| (String::from(*name), value)))
+        .collect();
+    if let Some(hostname) = hostname() {
+        result.insert(String::from("HOSTNAME"), hostname);
+    }
+    if let Some(user) = result.get("LOGNAME").filter(|_| !result.contains_key("USER")).cloned() {
+        result.insert(String::from("USER"), user);
+    }
+    if let Some(home) = result.get("HOME").cloned() {
+        for (name, path) in XDG_DEFAULTS {
+            result
+                .entry(String::from(*name))
+                .or_insert_with(|| format!("{home}/{path}"));
+        }
+    }
+    result
+}
+
+fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .into_iter()
+        .filter_map(|path| read_to_string(path).ok())
+        .map(|value| String::from(value.trim()))
+        .find(|value| !value.is_empty())
+}
+
+pub fn render(data: &[u8], variables: &Variables) -> Result<Vec<u8>, Vec<String>> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut missing: Vec<String> = Vec::new();
+    let mut rest = data;
+    while let Some(start) = rest.iter().position(|x| *x == b'@') {
+        result.extend_from_slice(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(tail) = rest.strip_prefix(b"@") {
+            result.push(b'@');
+            rest = tail;
+            continue;
+        }
+        let len = rest
+            .iter()
+            .take_while(|x| x.is_ascii_alphanumeric() || **x == b'_')
+            .count();
+        let is_name = len > 0 && !rest[0].is_ascii_digit() && rest.get(len) == Some(&b'@');
+        if !is_name {
+            result.push(b'@');
+            continue;
+        }
+        let name = String::from_utf8_lossy(&rest[..len]).into_owned();
+        match variables.get(&name) {
+            Some(value) => result.extend_from_slice(value.as_bytes()),
+            None if !missing.contains(&name) => missing.push(name),
+            None => {}
+        }
+        rest = &rest[len + 1..];
+    }
+    result.extend_from_slice(rest);
+    if missing.is_empty() {
+        Ok(result)
+    } else {
+        Err(missing)
+    }
+}
@@ -1,13 +1,29 @@
 use std::{
     error::Error,
-    fs::{canonicalize, create_dir, read_to_string, write},
-    os::unix::fs::symlink as create_symlink,
+    ffi::OsStr,
+    fs::{
+        canonicalize,
+        create_dir,
+        create_dir_all,
+        read,
+        read_to_string,
+        remove_dir_all,
+        remove_file,
+        set_permissions,
+        symlink_metadata,
+        write,
+        Permissions,
+    },
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink as create_symlink, MetadataExt, PermissionsExt},
+    },
     path::{Path, PathBuf},
 };
 
 use tempfile::tempdir;
 
-use crate::{command, handler, metadata};
+use crate::{command, handler, json, manifest, metadata, sha256, symlink, template, unified};
 
 #[test]
 fn register_ok() {
@@ -18,23 +34,596 @@ fn register_ok() {
     let target = String::from("target-file");
 
     handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
         metadata_path: root_path.join("makky.metadata").to_owned(),
-        source: source.clone(),
-        target: target.clone(),
+        mode: None,
+        owner: None,
+        source: source.clone().into(),
+        target: target.clone().into(),
     })
     .unwrap();
 
     let metadata = read_to_string(root_path.join("makky.metadata")).unwrap();
-    assert_eq!(metadata, format!("{source}\n{target}\n"));
+    assert_eq!(
+        metadata,
+        format!("makky-metadata\t1\nsource\t{source}\ntarget\t{target}\nend\nend-metadata\t1\n")
+    );
+}
+
+#[test]
+fn register_concurrent() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+
+    let threads: Vec<_> = (0..8)
+        .map(|idx| {
+            let metadata_path = metadata_path.clone();
+            std::thread::spawn(move || {
+                handler::register(command::ArgsRegister {
+                    deploy: symlink::Deploy::Symlink,
+                    dir_mode: None,
+                    group: None,
+                    metadata_path,
+                    mode: None,
+                    owner: None,
+                    source: format!("/source-{idx}").into(),
+                    target: format!("target-{idx}").into(),
+                })
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap().unwrap();
+    }
+
+    let metadata = read_to_string(&metadata_path).unwrap();
+    assert!(metadata.starts_with("makky-metadata\t1\n"));
+    for idx in 0..8 {
+        assert!(metadata.contains(&format!("source\t/source-{idx}\ntarget\ttarget-{idx}\nend\n")));
+    }
+}
+
+#[test]
+fn register_unsupported_format() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    write(&metadata_path, "/source\ntarget\n").unwrap();
+
+    let err = handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: String::from("/source-new").into(),
+        target: String::from("target-new").into(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "register: write new entry: unsupported format, run migrate first: {}",
+            metadata_path.display()
+        )
+    );
+}
+
+#[test]
+fn register_access() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let register = |target: &str, mode: &str| {
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Copy,
+            dir_mode: Some(String::from("700")),
+            group: Some(String::from("0")),
+            metadata_path: metadata_path.clone(),
+            mode: Some(String::from(mode)),
+            owner: Some(String::from("root")),
+            source: String::from("/source").into(),
+            target: String::from(target).into(),
+        })
+    };
+
+    register("target", "600").unwrap();
+    assert!(read(&metadata_path).unwrap().ends_with(
+        b"source\t/source\ntarget\ttarget\ndeploy\tcopy\ndir_mode\t700\ngroup\t0\nmode\t600\nowner\troot\nend\nend-metadata\t1\n"
+    ));
+
+    let err = register("other", "800").unwrap_err();
+    assert_eq!(err.to_string(), "register: create new entry: entry: invalid mode: 800");
+}
+
+#[test]
+fn register_link_special_paths() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join(OsStr::from_bytes(b"source\nwith\ttab\\and\xff"));
+    write(&source_path, "source").unwrap();
+    let target = OsStr::from_bytes(b"target\n\xfe");
+
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: target.to_owned(),
+    })
+    .unwrap();
+    let metadata = read(&metadata_path).unwrap();
+    assert_eq!(metadata.iter().filter(|x| **x == b'\n').count(), 5);
+    assert!(metadata.ends_with(b"source\\nwith\\ttab\\\\and\xff\ntarget\ttarget\\n\xfe\nend\nend-metadata\t1\n"));
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(&source_path, &root_path.join(target));
+}
+
+#[test]
+fn import_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let json_path = root_path.join("makky.json");
+    write(&metadata_path, "makky-metadata\t1\nsource\t/old\ntarget\told\nend\n").unwrap();
+    write(
+        &json_path,
+        r#"[
+            {"source": "/nix/store/a-makky_x", "target": ".config/x", "executable": false},
+            {"target": "new\nline \u00e9\ud83d\ude00", "source": "/nix/store/b-makky_y"},
+            {"source": "/nix/store/c-makky_z", "target": "z", "deploy": "copy"},
+            {"source": "/nix/store/d-makky_w", "target": "w", "deploy": "symlink"}
+        ]"#,
+    )
+    .unwrap();
+
+    handler::import(command::ArgsImport {
+        metadata_path: metadata_path.clone(),
+        json_path: json_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        "makky-metadata\t1\n\
+         source\t/nix/store/a-makky_x\ntarget\t.config/x\nend\n\
+         source\t/nix/store/b-makky_y\ntarget\tnew\\nline \u{e9}\u{1f600}\nend\n\
+         source\t/nix/store/c-makky_z\ntarget\tz\ndeploy\tcopy\nend\n\
+         source\t/nix/store/d-makky_w\ntarget\tw\nend\n\
+         end-metadata\t4\n"
+    );
+}
+
+#[test]
+fn import_invalid() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let json_path = root_path.join("makky.json");
+    let import = || {
+        handler::import(command::ArgsImport {
+            metadata_path: metadata_path.clone(),
+            json_path: json_path.clone(),
+        })
+    };
+
+    write(
+        &json_path,
+        r#"[
+            {"source": "/a", "target": "a"},
+            {"source": "b", "target": "b"},
+            {"source": "/c", "target": "/c"},
+            {"source": "/d", "target": "a"},
+            {"source": "/e"},
+            {"source": "/f", "target": 1},
+            "g",
+            {"source": "/h", "target": "h", "deploy": "hardcopy"},
+            {"source": "/i", "target": "i", "mode": "0900"},
+            {"source": "/j", "target": "j", "dir_mode": 700}
+        ]"#,
+    )
+    .unwrap();
+    let err = import().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "import: parse entries:\n\
+         \titem 1: new entry: source is not an absolute path: b\n\
+         \titem 2: new entry: target must be a relative path: /c\n\
+         \titem 3: new entry: target already registered: a\n\
+         \titem 4: field is missing or is not a string: target\n\
+         \titem 5: field is missing or is not a string: target\n\
+         \titem 6: not an object\n\
+         \titem 7: entry: unknown deploy mode: hardcopy\n\
+         \titem 8: entry: invalid mode: 0900\n\
+         \titem 9: field is missing or is not a string: dir_mode"
+    );
+    assert!(!metadata_path.exists());
+
+    for (data, expected) in [
+        ("{}", "json is not an array"),
+        ("", "parse json: unexpected end of input, expected value"),
+        ("[1, 2", "parse json: unexpected end of input, expected ',' or ']'"),
+        (
+            "[] x",
+            "parse json: unexpected character at offset 3, expected end of input",
+        ),
+        (
+            r#"[{"a" 1}]"#,
+            "parse json: unexpected character at offset 6, expected ':'",
+        ),
+        (r#"["\x"]"#, "parse json: invalid string at offset 2"),
+        (r#"["\ud800"]"#, "parse json: invalid string at offset 2"),
+        ("[1.2.3]", "parse json: invalid number at offset 1"),
+        ("[nul]", "parse json: unexpected character at offset 1, expected null"),
+    ] {
+        write(&json_path, data).unwrap();
+        assert_eq!(import().unwrap_err().to_string(), format!("import: {expected}"));
+    }
+}
+
+#[test]
+fn list_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    write(
+        &metadata_path,
+        "makky-metadata\t1\nsource\t/a\ntarget\ta\nend\nsource\t/b\ntarget\tb \"quoted\"\nmode\t0600\nend\nend-metadata\t2\n",
+    )
+    .unwrap();
+
+    let new_entries = metadata::list(metadata_path.clone()).unwrap();
+    let text: Vec<String> = new_entries.iter().map(ToString::to_string).collect();
+    assert_eq!(text, ["/a -> a", "/b -> b \"quoted\" mode=0600"]);
+    let items = new_entries
+        .iter()
+        .map(|new_entry| new_entry.to_json().unwrap())
+        .collect();
+    assert_eq!(
+        json::Value::Array(items).to_string(),
+        r#"[{"source":"/a","target":"a"},{"source":"/b","target":"b \"quoted\"","mode":"0600"}]"#
+    );
+
+    write(
+        &metadata_path,
+        b"makky-metadata\t1\nsource\t/a\xff\ntarget\ta\nend\nend-metadata\t1\n",
+    )
+    .unwrap();
+    let err = handler::list(command::ArgsList {
+        format: command::ListFormat::Json,
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "list: encode json: entry: path is not valid UTF-8: {}",
+            Path::new(OsStr::from_bytes(b"/a\xff")).display()
+        )
+    );
+}
+
+#[test]
+fn unregister_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let unregister = |target: &str, unlink: bool| {
+        handler::unregister(command::ArgsUnregister {
+            metadata_path: metadata_path.clone(),
+            target: target.into(),
+            unlink: unlink.then(|| root_path.clone()),
+        })
+    };
+
+    let file_link_a = LinkFile::create(&root_path, "a");
+    let file_link_b = LinkFile::create(&root_path, "b");
+    let file_link_c = LinkFile::create(&root_path, "c");
+    let mut metadata = read_to_string(&metadata_path).unwrap();
+    metadata.insert_str(metadata.find("source").unwrap(), "# comment\n\n");
+    write(&metadata_path, &metadata).unwrap();
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+
+    unregister("./b-file-target/", true).unwrap();
+    assert!(!file_link_b.target_path.is_symlink());
+    file_link_a.assert_target_created();
+    file_link_c.assert_target_created();
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        format!(
+            "makky-metadata\t1\n# comment\n\n\
+             source\t{}\ntarget\ta-file-target\nend\n\
+             source\t{}\ntarget\tc-file-target\nend\nend-metadata\t2\n",
+            file_link_a.source_path.display(),
+            file_link_c.source_path.display()
+        )
+    );
+
+    unregister("c-file-target", false).unwrap();
+    file_link_c.assert_target_created();
+    assert_eq!(metadata::list(metadata_path.clone()).unwrap().len(), 1);
+
+    let err = unregister("b-file-target", false).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unregister: remove entry: entry: target not registered: b-file-target"
+    );
+    let err = unregister("b-file-target", true).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unregister: remove entry: entry: target not registered: b-file-target"
+    );
+
+    let included_path = root_path.join("included.metadata");
+    write(
+        &included_path,
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\tb-file-target\nend\nend-metadata\t1\n",
+            file_link_b.source_path.display()
+        ),
+    )
+    .unwrap();
+    let metadata = read_to_string(&metadata_path)
+        .unwrap()
+        .replace("makky-metadata\t1\n", "makky-metadata\t1\ninclude\tincluded.metadata\n")
+        .replace("end-metadata\t1\n", "end-metadata\t2\n");
+    write(&metadata_path, &metadata).unwrap();
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    file_link_b.assert_target_created();
+    let err = unregister("b-file-target", true).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "unregister: remove entry: {}:2: entry: target registered in an included file: b-file-target",
+            included_path.display()
+        )
+    );
+    file_link_b.assert_target_created();
+    assert_eq!(read_to_string(&metadata_path).unwrap(), metadata);
+}
+
+#[test]
+fn unregister_keeps_other_directories() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    for target in ["x/a", "y/b"] {
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.clone(),
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: target.into(),
+        })
+        .unwrap();
+    }
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    remove_file(target_root.join("y/b")).unwrap();
+
+    handler::unregister(command::ArgsUnregister {
+        metadata_path: metadata_path.clone(),
+        target: "x/a".into(),
+        unlink: Some(target_root.clone()),
+    })
+    .unwrap();
+    assert!(!target_root.join("x").exists());
+    assert!(target_root.join("y").is_dir());
+}
+
+#[test]
+fn validate_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let validate = || {
+        handler::validate(command::ArgsValidate {
+            metadata_path: metadata_path.clone(),
+        })
+    };
+
+    write(
+        &metadata_path,
+        "makky-metadata\t1\n\
+         source\t/not-exists\ntarget\t.config/a\nend\n\
+         source\t/not-exists\ntarget\t.config/ab\nend\n\
+         end-metadata\t2\n",
+    )
+    .unwrap();
+    validate().unwrap();
+
+    write(
+        &metadata_path,
+        "makky-metadata\t1\n\
+         source\t/a\ntarget\t.config/a/b\nend\n\
+         source\trelative\ntarget\tb\nend\n\
+         source\t/c\ntarget\t/c\nend\n\
+         source\t/d\ntarget\t\nend\n\
+         source\t/e\ntarget\t./\nend\n\
+         source\t/f\ntarget\tb\nend\n\
+         source\t/g\ntarget\t./.config//a/b/\nend\n\
+         source\t/h\ntarget\t.config/a\nend\n\
+         source\t/i\ntarget\t.config/a/b/c\nend\n\
+         end-metadata\t9\n",
+    )
+    .unwrap();
+    let path = metadata_path.display();
+    assert_eq!(
+        validate().unwrap_err().to_string(),
+        format!(
+            "validate: parse entries:\n\
+             \t{path}:5: new entry: source is not an absolute path: relative\n\
+             \t{path}:8: new entry: target must be a relative path: /c\n\
+             \t{path}:11: entry: target is empty: \"\"\n\
+             \t{path}:14: entry: target is empty: \"./\"\n\
+             \t{path}:17: entry: target duplicate: /f -> b, previous entry: {path}:5\n\
+             \t{path}:20: entry: target is equivalent to a previous one: ./.config//a/b/, previous entry: {path}:2\n\
+             \t{path}:23: entry: target overlaps with .config/a/b: .config/a, previous entry: {path}:2\n\
+             \t{path}:26: entry: target overlaps with .config/a/b: .config/a/b/c, previous entry: {path}:2"
+        )
+    );
+}
+
+#[test]
+fn validate_trailer() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let validate = |data: &str| {
+        write(&metadata_path, data).unwrap();
+        handler::validate(command::ArgsValidate {
+            metadata_path: metadata_path.clone(),
+        })
+    };
+    let path = metadata_path.display();
+
+    validate("makky-metadata\t1\nend-metadata\t0\n").unwrap();
+    for (data, expected) in [
+        (
+            "makky-metadata\t1\n",
+            format!("{path}:1: parse trailer: missing, the file is truncated"),
+        ),
+        (
+            "makky-metadata\t1\nsource\t/a\ntarget\ta\nend\n",
+            format!("{path}:4: parse trailer: missing, the file is truncated"),
+        ),
+        (
+            "makky-metadata\t1\nsource\t/a\ntarget\ta\nend\nend-metadata\t2\n",
+            format!("{path}:5: parse trailer: expected 2 records, found 1"),
+        ),
+        (
+            "makky-metadata\t1\nend-metadata\tx\n",
+            format!("{path}:2: parse trailer: invalid count: x"),
+        ),
+        (
+            "makky-metadata\t1\nend-metadata\t0\nsource\t/a\ntarget\ta\nend\n",
+            format!("{path}:3: parse trailer: not the last line"),
+        ),
+    ] {
+        assert_eq!(validate(data).unwrap_err().to_string(), format!("validate: {expected}"));
+    }
+}
+
+#[test]
+fn migrate_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    write(
+        &metadata_path,
+        format!("{}\ntarget\n/other\nother\n", source_path.display()),
+    )
+    .unwrap();
+
+    handler::migrate(command::ArgsMigrate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap();
+    let expected = format!(
+        "makky-metadata\t1\nsource\t{}\ntarget\ttarget\nend\nsource\t/other\ntarget\tother\nend\nend-metadata\t2\n",
+        source_path.display()
+    );
+    assert_eq!(read_to_string(&metadata_path).unwrap(), expected);
+
+    handler::migrate(command::ArgsMigrate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(read_to_string(&metadata_path).unwrap(), expected);
+
+    write(&metadata_path, "/source\n#target\n").unwrap();
+    handler::migrate(command::ArgsMigrate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        "makky-metadata\t1\nsource\t/source\ntarget\t#target\nend\nend-metadata\t1\n"
+    );
+
+    write(&metadata_path, expected.replace("end-metadata\t2\n", "")).unwrap();
+    handler::migrate(command::ArgsMigrate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(read_to_string(&metadata_path).unwrap(), expected);
+
+    write(
+        &metadata_path,
+        format!(
+            "makky-metadata\t1\n# comment\n\nsource\t{}\n# comment\ntarget\ttarget\nextra\tvalue\nend\nend-metadata\t1\n\n",
+            source_path.display()
+        ),
+    )
+    .unwrap();
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(&source_path, &root_path.join("target"));
 }
 
 #[test]
 fn register_new_entry_source_not_absolute() {
     let source = String::from("makky-source-file-not-found");
     let err = handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
         metadata_path: PathBuf::from("/tmp/makky-config-root-not-found"),
-        source: source.clone(),
-        target: String::from("makky-target-file-not-found"),
+        mode: None,
+        owner: None,
+        source: source.clone().into(),
+        target: String::from("makky-target-file-not-found").into(),
     })
     .unwrap_err();
     assert!(err.source().is_some());
@@ -53,9 +642,14 @@ fn register_new_entry_source_not_absolute() {
 fn register_new_entry_source_is_absolute() {
     let target = String::from("/tmp/makky-target-file-not-found");
     let err = handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
         metadata_path: PathBuf::from("/tmp/makky-config-root-not-found"),
-        source: String::from("/tmp/makky-source-file-not-found"),
-        target: target.clone(),
+        mode: None,
+        owner: None,
+        source: String::from("/tmp/makky-source-file-not-found").into(),
+        target: target.clone().into(),
     })
     .unwrap_err();
     assert!(err.source().is_some());
@@ -84,9 +678,14 @@ impl LinkFile {
         let target_relative_path = format!("{prefix}-file-target");
         let target_path = root_path.join(&target_relative_path).to_owned();
         handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
             metadata_path: root_path.join("makky.metadata").to_owned(),
-            source: source_path.to_string_lossy().into_owned(),
-            target: target_relative_path,
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: target_relative_path.into(),
         })
         .unwrap();
         LinkFile {
@@ -134,9 +733,14 @@ impl LinkDirectory {
         let target_path = root_path.join(&target_relative_path).to_owned();
         let target_file_path = target_path.join("file").to_owned();
         handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
             metadata_path: root_path.join("makky.metadata").to_owned(),
-            source: source_path.to_string_lossy().into_owned(),
-            target: target_relative_path,
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: target_relative_path.into(),
         })
         .unwrap();
         LinkDirectory {
@@ -184,9 +788,6 @@ fn link_unlink_ok() {
 
     let file_link_vacant_not_present = LinkFile::create(&root_path, "vacant-not-present");
 
-    let file_link_vacant_present = LinkFile::create(&root_path, "vacant-present");
-    create_symlink(&file_x_path, &file_link_vacant_present.target_path).unwrap();
-
     let directory_link_equals = LinkDirectory::create(&root_path, &root_path, "equals");
     create_symlink(&directory_link_equals.source_path, &directory_link_equals.target_path).unwrap();
 
@@ -204,13 +805,23 @@ fn link_unlink_ok() {
     let directory_link_vacant_not_present = LinkDirectory::create(&root_path, &root_path, "vacant-not-present");
 
     let directory_link_vacant_present = LinkDirectory::create(&root_path, &root_path, "vacant-present");
+    let directory_link_vacant_present_other_path = directory_link_vacant_present.source_path.join("other");
+    write(&directory_link_vacant_present_other_path, "other").unwrap();
     create_dir(&directory_link_vacant_present.target_path).unwrap();
-    create_symlink(&file_x_path, &directory_link_vacant_present.target_file_path).unwrap();
+    create_symlink(
+        &directory_link_vacant_present_other_path,
+        &directory_link_vacant_present.target_file_path,
+    )
+    .unwrap();
 
     let metadata_path = root_path.join("makky.metadata");
+    let manifest_path = root_path.join("makky.metadata.manifest");
 
     for _ in 0..2 {
         handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
             metadata_path: metadata_path.clone(),
             target_root: root_path.clone(),
         })
@@ -219,7 +830,6 @@ fn link_unlink_ok() {
 
     assert_symlink_equals(&file_link_equals.source_path, &file_link_equals.target_path);
     file_link_vacant_not_present.assert_target_created();
-    file_link_vacant_present.assert_target_created();
     assert_symlink_equals(&directory_link_equals.source_path, &directory_link_equals.target_path);
     directory_link_level_0.assert_target_created();
     directory_link_level_1.assert_target_created();
@@ -232,7 +842,7 @@ fn link_unlink_ok() {
         .unwrap()
         .map(|x| x.unwrap().path().to_string_lossy().into_owned())
         .collect();
-    assert_eq!(entries.len(), 18);
+    assert_eq!(entries.len(), 17);
     entries.sort();
     let mut expected: Vec<&Path> = vec![
         directory_link_equals.source_path.as_ref(),
@@ -249,10 +859,9 @@ fn link_unlink_ok() {
         file_link_equals.target_path.as_ref(),
         file_link_vacant_not_present.source_path.as_ref(),
         file_link_vacant_not_present.target_path.as_ref(),
-        file_link_vacant_present.source_path.as_ref(),
-        file_link_vacant_present.target_path.as_ref(),
         file_x_path.as_ref(),
         metadata_path.as_ref(),
+        manifest_path.as_ref(),
     ];
     expected.sort();
     let expected = expected.into_iter().enumerate();
@@ -261,6 +870,7 @@ fn link_unlink_ok() {
     }
 
     handler::unlink(command::ArgsUnlink {
+        dry_run: false,
         metadata_path: metadata_path.clone(),
         target_root: root_path,
     })
@@ -268,51 +878,564 @@ fn link_unlink_ok() {
 
     file_link_equals.assert_target_removed();
     file_link_vacant_not_present.assert_target_removed();
-    file_link_vacant_present.assert_target_removed();
     directory_link_equals.assert_target_removed();
     directory_link_level_0.assert_target_removed();
     directory_link_level_1.assert_target_removed();
     directory_link_vacant_equals.assert_target_removed();
     directory_link_vacant_not_present.assert_target_removed();
     directory_link_vacant_present.assert_target_removed();
+    assert!(!manifest_path.exists());
 }
 
 #[test]
-fn link_entry_source_not_exists() {
+fn link_unlink_dry_run() {
     let root = tempdir().unwrap();
     let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
 
-    let source_path = root_path.join("not-exists-source");
-    let metadata_path = root_path.join("makky.metadata").to_owned();
+    let file_x_path = root_path.join("file-x");
+    write(&file_x_path, "file-x").unwrap();
 
+    let file_link_nested_source_path = root_path.join("nested-file-source");
+    write(&file_link_nested_source_path, "nested-file-source-content").unwrap();
     handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
         metadata_path: metadata_path.clone(),
-        source: source_path.to_string_lossy().into_owned(),
-        target: String::from("not-exists-target"),
+        mode: None,
+        owner: None,
+        source: file_link_nested_source_path.as_os_str().to_owned(),
+        target: String::from("nested/directory/file-target").into(),
     })
     .unwrap();
+    let file_link_nested_target_path = root_path.join("nested/directory/file-target");
 
-    let err = handler::link(command::ArgsLink {
-        metadata_path: metadata_path.clone(),
-        target_root: root_path.clone(),
-    })
-    .unwrap_err();
-
-    assert!(err.source().is_some());
+    let file_link_vacant_present = LinkFile::create(&root_path, "vacant-present");
+    create_symlink(&file_x_path, &file_link_vacant_present.target_path).unwrap();
 
-    assert_eq!(
+    let mut plan = symlink::Plan::new(
+        symlink::Conflict::Overwrite,
+        symlink::CrossDevice::Fail,
+        &Default::default(),
+    );
+    plan.create(
+        &file_link_nested_source_path,
+        &file_link_nested_target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    plan.create(
+        &file_link_vacant_present.source_path,
+        &file_link_vacant_present.target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        &[
+            symlink::Operation::CreateDirectory {
+                path: root_path.join("nested"),
+                attributes: Default::default(),
+            },
+            symlink::Operation::CreateDirectory {
+                path: root_path.join("nested/directory"),
+                attributes: Default::default(),
+            },
+            symlink::Operation::CreateSymlink {
+                source: file_link_nested_source_path.clone(),
+                target: file_link_nested_target_path.clone(),
+            },
+            symlink::Operation::Overwrite(file_link_vacant_present.target_path.clone()),
+            symlink::Operation::CreateSymlink {
+                source: file_link_vacant_present.source_path.clone(),
+                target: file_link_vacant_present.target_path.clone(),
+            },
+        ]
+    );
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: true,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert!(!root_path.join("nested").exists());
+    assert_symlink_equals(&file_x_path, &file_link_vacant_present.target_path);
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(&file_link_nested_source_path, &file_link_nested_target_path);
+    file_link_vacant_present.assert_target_created();
+
+    let mut plan = symlink::Plan::default();
+    plan.remove(&file_link_nested_source_path, &file_link_nested_target_path)
+        .unwrap();
+    assert_eq!(
+        plan.operations(),
+        &[symlink::Operation::RemoveSymlink(file_link_nested_target_path.clone())]
+    );
+
+    handler::unlink(command::ArgsUnlink {
+        dry_run: true,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(&file_link_nested_source_path, &file_link_nested_target_path);
+    file_link_vacant_present.assert_target_created();
+}
+
+#[test]
+fn apply_rollback() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+
+    let file_link_created = LinkFile::create(&root_path, "created");
+    let directory_link_replaced = LinkDirectory::create(&root_path, &root_path, "replaced");
+    let directory_link_replaced_other_path = directory_link_replaced.source_path.join("other");
+    write(&directory_link_replaced_other_path, "other").unwrap();
+    create_dir(&directory_link_replaced.target_path).unwrap();
+    create_symlink(
+        &directory_link_replaced_other_path,
+        &directory_link_replaced.target_file_path,
+    )
+    .unwrap();
+    let file_link_removed = LinkFile::create(&root_path, "removed");
+    create_symlink(&file_link_removed.source_path, &file_link_removed.target_path).unwrap();
+    let directory_link = LinkDirectory::create(&root_path, &root_path, "created");
+    let file_link_failed = LinkFile::create(&root_path, "failed");
+
+    let mut plan = symlink::Plan::default();
+    plan.create(
+        &file_link_created.source_path,
+        &file_link_created.target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    plan.create(
+        &directory_link_replaced.source_path,
+        &directory_link_replaced.target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    plan.remove(&file_link_removed.source_path, &file_link_removed.target_path)
+        .unwrap();
+    plan.create(
+        &directory_link.source_path,
+        &directory_link.target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    plan.create(
+        &file_link_failed.source_path,
+        &file_link_failed.target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+
+    write(&file_link_failed.target_path, "file-x").unwrap();
+    let mut applied = Vec::new();
+    let err = plan.apply(|operation| applied.push(operation.to_string())).unwrap_err();
+    assert!(err.source().is_some());
+    assert!(err.to_string().starts_with(&format!(
+        "create new symlink: {} -> {}: ",
+        file_link_failed.source_path.display(),
+        file_link_failed.target_path.display()
+    )));
+    let operations = plan.operations();
+    assert_eq!(
+        applied,
+        operations[..operations.len() - 1]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+    );
+
+    file_link_created.assert_target_removed();
+    assert!(!file_link_created.target_path.is_symlink());
+    assert_symlink_equals(
+        &directory_link_replaced_other_path,
+        &directory_link_replaced.target_file_path,
+    );
+    assert!(!directory_link_replaced.target_path.join("other").exists());
+    assert_symlink_equals(&file_link_removed.source_path, &file_link_removed.target_path);
+    assert!(!directory_link.target_path.exists());
+    assert_eq!(read_to_string(&file_link_failed.target_path).unwrap(), "file-x");
+}
+
+#[test]
+fn switch_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let metadata_old_path = root_path.join("makky-old.metadata");
+    let metadata_new_path = root_path.join("makky-new.metadata");
+
+    let register = |metadata_path: &Path, name: &str, target: &str| {
+        let source_path = root_path.join(name);
+        write(&source_path, name).unwrap();
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.to_owned(),
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: String::from(target).into(),
+        })
+        .unwrap();
+        source_path
+    };
+    let source_kept_path = register(&metadata_old_path, "kept-source", "kept-target");
+    let source_removed_path = register(&metadata_old_path, "removed-source", "removed-target");
+    let source_changed_old_path = register(&metadata_old_path, "changed-old-source", "changed-target");
+    register(&metadata_new_path, "kept-source", "kept-target");
+    let source_changed_new_path = register(&metadata_new_path, "changed-new-source", "changed-target");
+    let source_added_path = register(&metadata_new_path, "added-source", "added-target");
+
+    let target_kept_path = root_path.join("kept-target");
+    let target_removed_path = root_path.join("removed-target");
+    let target_changed_path = root_path.join("changed-target");
+    let target_added_path = root_path.join("added-target");
+
+    handler::switch(command::ArgsSwitch {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        new_metadata_path: metadata_old_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        read_to_string(&metadata_old_path).unwrap()
+    );
+    assert_symlink_equals(&source_kept_path, &target_kept_path);
+    assert_symlink_equals(&source_removed_path, &target_removed_path);
+    assert_symlink_equals(&source_changed_old_path, &target_changed_path);
+    assert!(!target_added_path.exists());
+    let target_kept_inode = symlink_metadata(&target_kept_path).unwrap().ino();
+
+    handler::switch(command::ArgsSwitch {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        new_metadata_path: metadata_new_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        read_to_string(&metadata_new_path).unwrap()
+    );
+    assert_symlink_equals(&source_kept_path, &target_kept_path);
+    assert_eq!(symlink_metadata(&target_kept_path).unwrap().ino(), target_kept_inode);
+    assert!(!target_removed_path.exists());
+    assert!(!target_removed_path.is_symlink());
+    assert_symlink_equals(&source_changed_new_path, &target_changed_path);
+    assert_symlink_equals(&source_added_path, &target_added_path);
+
+    remove_file(&target_kept_path).unwrap();
+    handler::switch(command::ArgsSwitch {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        new_metadata_path: metadata_new_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(&source_kept_path, &target_kept_path);
+    assert_symlink_equals(&source_changed_new_path, &target_changed_path);
+    assert_symlink_equals(&source_added_path, &target_added_path);
+}
+
+#[test]
+fn switch_removed_target_replaced() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let metadata_old_path = root_path.join("makky-old.metadata");
+    let metadata_new_path = root_path.join("makky-new.metadata");
+
+    let register = |metadata_path: &Path, name: &str, target: &str| {
+        let source_path = root_path.join(name);
+        write(&source_path, name).unwrap();
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.to_owned(),
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: String::from(target).into(),
+        })
+        .unwrap();
+        source_path
+    };
+    register(&metadata_old_path, "a-source", ".a");
+    let source_b_path = register(&metadata_old_path, "b-source", ".b");
+    register(&metadata_new_path, "b-source", ".b");
+    let target_a_path = root_path.join(".a");
+    let target_b_path = root_path.join(".b");
+
+    let args = |new_metadata_path: &Path| command::ArgsSwitch {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        new_metadata_path: new_metadata_path.to_owned(),
+        target_root: root_path.clone(),
+    };
+    handler::switch(args(&metadata_old_path)).unwrap();
+    remove_file(&target_a_path).unwrap();
+    write(&target_a_path, "user").unwrap();
+
+    handler::switch(args(&metadata_new_path)).unwrap();
+    assert_eq!(read_to_string(&target_a_path).unwrap(), "user");
+    assert!(!target_a_path.is_symlink());
+    assert_symlink_equals(&source_b_path, &target_b_path);
+    assert_eq!(
+        read_to_string(&metadata_path).unwrap(),
+        read_to_string(&metadata_new_path).unwrap()
+    );
+}
+
+#[test]
+fn switch_changed_symlink_replaced() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let source_old_path = root_path.join("old-source");
+    let source_new_path = root_path.join("new-source");
+    let target_path = root_path.join("target");
+    write(&source_old_path, "old").unwrap();
+    write(&source_new_path, "new").unwrap();
+    create_symlink(&source_old_path, &target_path).unwrap();
+
+    let mut plan = symlink::Plan::default();
+    plan.remove(&source_old_path, &target_path).unwrap();
+    plan.create(
+        &source_new_path,
+        &target_path,
+        symlink::Deploy::Symlink,
+        &Default::default(),
+    )
+    .unwrap();
+    plan.finish();
+    assert_eq!(
+        plan.operations(),
+        &[symlink::Operation::ReplaceSymlink {
+            source: source_new_path.clone(),
+            target: target_path.clone(),
+        }]
+    );
+    plan.apply(|_| {}).unwrap();
+    assert_symlink_equals(&source_new_path, &target_path);
+    assert!(!root_path.join(".target.makky-copy").exists());
+}
+
+#[test]
+fn status_ok() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+
+    let file_link = LinkFile::create(&root_path, "file");
+    let directory_link = LinkDirectory::create(&root_path, &root_path, "directory");
+
+    let args = || command::ArgsStatus {
+        metadata_path: root_path.join("makky.metadata"),
+        target_root: root_path.clone(),
+    };
+    let err = handler::status(args()).unwrap_err();
+    assert!(err.source().is_none());
+    assert_eq!(err.to_string(), "status: entries drifted: 2");
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: root_path.join("makky.metadata"),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    file_link.assert_target_created();
+    directory_link.assert_target_created();
+    handler::status(args()).unwrap();
+}
+
+#[test]
+fn status_drifted() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+
+    let file_x_path = root_path.join("file-x");
+    write(&file_x_path, "file-x").unwrap();
+
+    let file_link_linked = LinkFile::create(&root_path, "linked");
+    create_symlink(&file_link_linked.source_path, &file_link_linked.target_path).unwrap();
+
+    let file_link_missing = LinkFile::create(&root_path, "missing");
+
+    let file_link_occupied = LinkFile::create(&root_path, "occupied");
+    write(&file_link_occupied.target_path, "file-x").unwrap();
+
+    let file_link_points_elsewhere = LinkFile::create(&root_path, "points-elsewhere");
+    create_symlink(&file_x_path, &file_link_points_elsewhere.target_path).unwrap();
+
+    let file_link_dangling = LinkFile::create(&root_path, "dangling");
+    create_symlink(root_path.join("not-exists"), &file_link_dangling.target_path).unwrap();
+
+    let directory_link_linked = LinkDirectory::create(&root_path, &root_path, "linked");
+    create_dir(&directory_link_linked.target_path).unwrap();
+    create_symlink(
+        &directory_link_linked.source_file_path,
+        &directory_link_linked.target_file_path,
+    )
+    .unwrap();
+
+    let directory_link_missing = LinkDirectory::create(&root_path, &root_path, "missing");
+    create_dir(&directory_link_missing.target_path).unwrap();
+
+    let directory_link_partial = LinkDirectory::create(&root_path, &root_path, "partial");
+    write(directory_link_partial.source_path.join("other"), "other").unwrap();
+    create_dir(&directory_link_partial.target_path).unwrap();
+    create_symlink(
+        &directory_link_partial.source_file_path,
+        &directory_link_partial.target_file_path,
+    )
+    .unwrap();
+
+    let expected = [
+        (
+            &file_link_linked.source_path,
+            &file_link_linked.target_path,
+            symlink::Status::Linked,
+        ),
+        (
+            &file_link_missing.source_path,
+            &file_link_missing.target_path,
+            symlink::Status::Missing,
+        ),
+        (
+            &file_link_occupied.source_path,
+            &file_link_occupied.target_path,
+            symlink::Status::Occupied,
+        ),
+        (
+            &file_link_points_elsewhere.source_path,
+            &file_link_points_elsewhere.target_path,
+            symlink::Status::PointsElsewhere,
+        ),
+        (
+            &file_link_dangling.source_path,
+            &file_link_dangling.target_path,
+            symlink::Status::Dangling,
+        ),
+        (
+            &directory_link_linked.source_path,
+            &directory_link_linked.target_path,
+            symlink::Status::Linked,
+        ),
+        (
+            &directory_link_missing.source_path,
+            &directory_link_missing.target_path,
+            symlink::Status::Missing,
+        ),
+        (
+            &directory_link_partial.source_path,
+            &directory_link_partial.target_path,
+            symlink::Status::Partial,
+        ),
+    ];
+    for (source_path, target_path, status) in expected {
+        assert_eq!(
+            symlink::status(
+                source_path,
+                target_path,
+                symlink::Deploy::Symlink,
+                &Default::default(),
+                &Default::default()
+            )
+            .unwrap(),
+            status
+        );
+    }
+
+    let err = handler::status(command::ArgsStatus {
+        metadata_path: root_path.join("makky.metadata"),
+        target_root: root_path.clone(),
+    })
+    .unwrap_err();
+    assert!(err.source().is_none());
+    assert_eq!(err.to_string(), "status: entries drifted: 6");
+}
+
+#[test]
+fn link_entry_source_not_exists() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+
+    let source_path = root_path.join("not-exists-source");
+    let metadata_path = root_path.join("makky.metadata").to_owned();
+
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: String::from("not-exists-target").into(),
+    })
+    .unwrap();
+
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap_err();
+
+    assert!(err.source().is_some());
+
+    assert_eq!(
         err.to_string(),
         format!(
-            "link: read metadata: parse entries:\n\tentry: source not exists: {}",
+            "link: read metadata: parse entries:\n\t{}:2: entry: source not exists: {}",
+            metadata_path.display(),
             source_path.display()
         )
     );
     if let handler::Error::LinkReadMetadata(metadata::Error::ParseEntries(errors)) = err {
         assert_eq!(errors.len(), 1);
         let entry_error = &errors[0];
-        assert!(entry_error.source().is_none());
-        if let metadata::Error::EntrySourceNotExists(path) = entry_error {
-            assert_eq!(path, &source_path);
+        assert!(entry_error.source().is_some());
+        if let metadata::Error::Located { err, location } = entry_error {
+            assert_eq!(location.path, metadata_path);
+            assert_eq!(location.line, 2);
+            assert!(matches!(err.as_ref(), metadata::Error::EntrySourceNotExists(path) if path == &source_path));
         } else {
             panic!("Unexpected entry error: {:?}", entry_error);
         }
@@ -321,6 +1444,49 @@ fn link_entry_source_not_exists() {
     }
 }
 
+#[test]
+fn unlink_entry_source_not_exists() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+
+    let file_link = LinkFile::create(&root_path, "file");
+    let directory_link = LinkDirectory::create(&root_path, &root_path, "directory");
+    let directory_link_equals = LinkDirectory::create(&root_path, &root_path, "equals");
+    create_symlink(&directory_link_equals.source_path, &directory_link_equals.target_path).unwrap();
+    let nested_source_path = directory_link.source_path.join("nested");
+    create_dir(&nested_source_path).unwrap();
+    write(nested_source_path.join("file"), "nested").unwrap();
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    file_link.assert_target_created();
+    directory_link.assert_target_created();
+    let nested_target_path = directory_link.target_path.join("nested/file");
+    assert!(nested_target_path.is_symlink());
+
+    remove_file(&file_link.source_path).unwrap();
+    remove_dir_all(&directory_link.source_path).unwrap();
+    remove_dir_all(&directory_link_equals.source_path).unwrap();
+
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert!(!file_link.target_path.is_symlink());
+    assert!(!directory_link.target_file_path.is_symlink());
+    assert!(!nested_target_path.is_symlink());
+    assert!(!directory_link_equals.target_path.is_symlink());
+}
+
 #[test]
 fn link_entry_target_exists() {
     let root = tempdir().unwrap();
@@ -330,6 +1496,9 @@ fn link_entry_target_exists() {
     write(&file_link_occupied.target_path, "file-x").unwrap();
 
     let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
         metadata_path: root_path.join("makky.metadata").to_owned(),
         target_root: root_path.clone(),
     })
@@ -340,16 +1509,20 @@ fn link_entry_target_exists() {
     assert_eq!(
         err.to_string(),
         format!(
-            "link: read metadata: parse entries:\n\tentry: target already exists: {}",
+            "link: read metadata: parse entries:\n\t{}:2: entry: target already exists: {}",
+            root_path.join("makky.metadata").display(),
             file_link_occupied.target_path.display(),
         )
     );
     if let handler::Error::LinkReadMetadata(metadata::Error::ParseEntries(errors)) = err {
         assert_eq!(errors.len(), 1);
         let entry_error = &errors[0];
-        assert!(entry_error.source().is_none());
-        if let metadata::Error::EntryTargetExists(path) = entry_error {
-            assert_eq!(path, &file_link_occupied.target_path);
+        assert!(entry_error.source().is_some());
+        if let metadata::Error::Located { err, location } = entry_error {
+            assert_eq!(location.line, 2);
+            assert!(
+                matches!(err.as_ref(), metadata::Error::EntryTargetExists(path) if path == &file_link_occupied.target_path)
+            );
         } else {
             panic!("Unexpected entry error: {:?}", entry_error);
         }
@@ -359,104 +1532,1605 @@ fn link_entry_target_exists() {
 }
 
 #[test]
-fn link_entry_target_duplicate() {
+fn link_conflict_backup() {
     let root = tempdir().unwrap();
     let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
 
-    let source_path = root_path.join("source").to_owned();
-    write(&source_path, "source-content").unwrap();
+    let file_link_occupied = LinkFile::create(&root_path, "occupied");
+    write(&file_link_occupied.target_path, "file-x").unwrap();
+    let backup_path = root_path.join("occupied-file-target.makky-backup");
+    write(&backup_path, "file-y").unwrap();
+    let backup_numbered_path = root_path.join("occupied-file-target.makky-backup.1");
 
-    let metadata_path = root_path.join("makky.metadata").to_owned();
+    let directory_link_occupied = LinkDirectory::create(&root_path, &root_path, "occupied");
+    write(&directory_link_occupied.target_path, "file-z").unwrap();
+    let directory_backup_path = root_path.join("occupied-directory-target.makky-backup");
 
-    for _ in 0..2 {
-        handler::register(command::ArgsRegister {
-            metadata_path: metadata_path.clone(),
-            source: source_path.to_string_lossy().into_owned(),
-            target: String::from("target"),
-        })
-        .unwrap();
-    }
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Backup,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    file_link_occupied.assert_target_created();
+    directory_link_occupied.assert_target_created();
+    assert_eq!(read_to_string(&backup_path).unwrap(), "file-y");
+    assert_eq!(read_to_string(&backup_numbered_path).unwrap(), "file-x");
+    assert_eq!(read_to_string(&directory_backup_path).unwrap(), "file-z");
+    assert!(root_path.join("makky.metadata.manifest").exists());
+
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_eq!(read_to_string(&file_link_occupied.target_path).unwrap(), "file-x");
+    assert!(!backup_numbered_path.exists());
+    assert_eq!(read_to_string(&backup_path).unwrap(), "file-y");
+    assert_eq!(read_to_string(&directory_link_occupied.target_path).unwrap(), "file-z");
+    assert!(!directory_backup_path.exists());
+    assert!(!root_path.join("makky.metadata.manifest").exists());
+}
+
+#[test]
+fn link_conflict_overwrite() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+
+    let file_link_occupied_file = LinkFile::create(&root_path, "occupied-file");
+    write(&file_link_occupied_file.target_path, "file-x").unwrap();
+
+    let file_link_occupied_directory = LinkFile::create(&root_path, "occupied-directory");
+    create_dir(&file_link_occupied_directory.target_path).unwrap();
+    write(file_link_occupied_directory.target_path.join("file"), "file-x").unwrap();
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: root_path.join("makky.metadata"),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    file_link_occupied_file.assert_target_created();
+    file_link_occupied_directory.assert_target_created();
+    assert_eq!(root_path.read_dir().unwrap().count(), 6);
+}
+
+#[test]
+fn link_unlink_target_foreign() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+
+    let file_x_path = root_path.join("file-x");
+    write(&file_x_path, "file-x").unwrap();
+
+    let file_link_foreign = LinkFile::create(&root_path, "foreign");
+    create_symlink(&file_x_path, &file_link_foreign.target_path).unwrap();
 
     let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
         metadata_path: metadata_path.clone(),
-        target_root: root_path,
+        target_root: root_path.clone(),
     })
     .unwrap_err();
     assert!(err.source().is_some());
     assert_eq!(
         err.to_string(),
         format!(
-            "link: read metadata: parse entries:\n\tentry: target duplicate: {} -> target",
-            source_path.display()
+            "link: create {} -> {}: target is a foreign symlink: {}",
+            file_link_foreign.source_path.display(),
+            file_link_foreign.target_path.display(),
+            file_link_foreign.target_path.display()
         )
     );
-    if let handler::Error::LinkReadMetadata(metadata_err) = err {
-        assert!(metadata_err.source().is_none());
-    } else {
-        panic!("Unexpected error: {:?}", err);
-    }
-}
+    assert_symlink_equals(&file_x_path, &file_link_foreign.target_path);
 
-#[test]
-fn link_invalid_target_root() {
-    let err = handler::link(command::ArgsLink {
-        metadata_path: PathBuf::from("/tmp/makky"),
-        target_root: PathBuf::from("makky"),
+    let err = handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
     })
     .unwrap_err();
     assert!(err.source().is_some());
     assert_eq!(
         err.to_string(),
-        "link: read metadata: target root is not an absolute path: makky"
+        format!(
+            "link: remove {} -> {}: target is a foreign symlink: {}",
+            file_link_foreign.source_path.display(),
+            file_link_foreign.target_path.display(),
+            file_link_foreign.target_path.display()
+        )
     );
+    assert_symlink_equals(&file_x_path, &file_link_foreign.target_path);
 
-    let err = handler::link(command::ArgsLink {
-        metadata_path: PathBuf::from("/tmp/makky"),
-        target_root: PathBuf::from("/tmp/makky"),
+    let directory_link_foreign = LinkDirectory::create(&root_path, &root_path, "foreign");
+    create_dir(&directory_link_foreign.target_path).unwrap();
+    create_symlink(&file_x_path, &directory_link_foreign.target_file_path).unwrap();
+
+    let mut plan = symlink::Plan::default();
+    let err = plan
+        .create(
+            &directory_link_foreign.source_path,
+            &directory_link_foreign.target_path,
+            symlink::Deploy::Symlink,
+            &Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "target is a foreign symlink: {}",
+            directory_link_foreign.target_file_path.display()
+        )
+    );
+
+    let mut plan = symlink::Plan::default();
+    plan.remove(&directory_link_foreign.source_path, &directory_link_foreign.target_path)
+        .unwrap();
+    assert!(plan.operations().is_empty());
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
     })
-    .unwrap_err();
-    assert!(err.source().is_some());
+    .unwrap();
+    file_link_foreign.assert_target_created();
+    directory_link_foreign.assert_target_created();
+}
+
+#[test]
+fn link_unlink_dangling() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let link_args = || command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    };
+
+    let source_old_path = root_path.join("old-source");
+    write(&source_old_path, "old-source").unwrap();
+    let source_new_path = root_path.join("new-source");
+    write(&source_new_path, "new-source").unwrap();
+    let target_path = root_path.join("target");
+
+    write(&metadata_path, format!("{}\ntarget\n", source_old_path.display())).unwrap();
+    handler::link(link_args()).unwrap();
+    remove_file(&source_old_path).unwrap();
+    assert_eq!(
+        symlink::status(
+            &source_new_path,
+            &target_path,
+            symlink::Deploy::Symlink,
+            &Default::default(),
+            &Default::default()
+        )
+        .unwrap(),
+        symlink::Status::Dangling
+    );
+
+    write(&metadata_path, format!("{}\ntarget\n", source_new_path.display())).unwrap();
+    handler::link(link_args()).unwrap();
+    assert_symlink_equals(&source_new_path, &target_path);
+
+    handler::migrate(command::ArgsMigrate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap();
+    let directory_link = LinkDirectory::create(&root_path, &root_path, "dangling");
+    handler::link(link_args()).unwrap();
+    directory_link.assert_target_created();
+    remove_file(&directory_link.source_file_path).unwrap();
+    assert!(directory_link.target_file_path.is_symlink());
+
+    let file_link_foreign = LinkFile::create(&root_path, "foreign");
+    create_symlink(root_path.join("not-exists"), &file_link_foreign.target_path).unwrap();
+    let err = handler::link(link_args()).unwrap_err();
     assert_eq!(
         err.to_string(),
-        "link: read metadata: target root is not a directory: /tmp/makky"
+        format!(
+            "link: create {} -> {}: target is a foreign symlink: {}",
+            file_link_foreign.source_path.display(),
+            file_link_foreign.target_path.display(),
+            file_link_foreign.target_path.display()
+        )
     );
+    remove_file(&file_link_foreign.target_path).unwrap();
+
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert!(!target_path.is_symlink());
+    assert!(!directory_link.target_file_path.is_symlink());
 }
 
 #[test]
-fn link_invalid_config() {
+fn unlink_remove_created_directories() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let manifest_path = root_path.join("makky.metadata.manifest");
+
+    let source_path = root_path.join("source");
+    create_dir_all(source_path.join("nested")).unwrap();
+    write(source_path.join("nested/file"), "file").unwrap();
+    let source_file_path = root_path.join("source-file");
+    write(&source_file_path, "source-file").unwrap();
+    let existing_path = root_path.join("existing");
+    create_dir(&existing_path).unwrap();
+    write(
+        &metadata_path,
+        format!(
+            "{}\ncreated/directory\n{}\nexisting/created/file\n",
+            source_path.display(),
+            source_file_path.display()
+        ),
+    )
+    .unwrap();
+
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert_symlink_equals(
+        &source_path.join("nested/file"),
+        &root_path.join("created/directory/nested/file"),
+    );
+    assert_symlink_equals(&source_file_path, &root_path.join("existing/created/file"));
+    let manifest = read_to_string(&manifest_path).unwrap();
+    for path in [
+        "created",
+        "created/directory",
+        "created/directory/nested",
+        "existing/created",
+    ] {
+        assert!(manifest.contains(&format!("directory\t{}\n", root_path.join(path).display())));
+    }
+    assert!(!manifest.contains(&format!("directory\t{}\n", existing_path.display())));
+
+    write(root_path.join("created/user-file"), "user-file").unwrap();
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap();
+    assert!(!root_path.join("created/directory").exists());
+    assert!(root_path.join("created/user-file").exists());
+    assert!(!root_path.join("existing/created").exists());
+    assert!(existing_path.is_dir());
+    let manifest = read_to_string(&manifest_path).unwrap();
+    assert_eq!(
+        manifest,
+        format!("directory\t{}\n", root_path.join("created").display())
+    );
+}
+
+#[test]
+fn link_prune_stale_directory_entries() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let link_args = || command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    };
+
+    let source_old_path = root_path.join("source-old");
+    create_dir_all(source_old_path.join("nested")).unwrap();
+    write(source_old_path.join("file"), "file").unwrap();
+    write(source_old_path.join("removed"), "removed").unwrap();
+    write(source_old_path.join("nested/file"), "nested").unwrap();
+    let source_new_path = root_path.join("source-new");
+    create_dir(&source_new_path).unwrap();
+    write(source_new_path.join("file"), "file").unwrap();
+    let target_path = root_path.join("target");
+
+    write(&metadata_path, format!("{}\ntarget\n", source_old_path.display())).unwrap();
+    handler::link(link_args()).unwrap();
+    assert_symlink_equals(&source_old_path.join("removed"), &target_path.join("removed"));
+    let foreign_path = target_path.join("foreign");
+    create_symlink(root_path.join("not-exists"), &foreign_path).unwrap();
+
+    write(&metadata_path, format!("{}\ntarget\n", source_new_path.display())).unwrap();
+    handler::link(link_args()).unwrap();
+    assert_symlink_equals(&source_new_path.join("file"), &target_path.join("file"));
+    assert!(!target_path.join("removed").is_symlink());
+    assert!(!target_path.join("nested").exists());
+    assert!(foreign_path.is_symlink());
+
+    remove_file(source_new_path.join("file")).unwrap();
+    handler::link(link_args()).unwrap();
+    assert!(!target_path.join("file").is_symlink());
+    assert!(foreign_path.is_symlink());
+}
+
+#[test]
+fn link_entry_target_duplicate() {
     let root = tempdir().unwrap();
     let root_path = root.path().to_owned();
+
+    let source_path = root_path.join("source").to_owned();
+    write(&source_path, "source-content").unwrap();
+
     let metadata_path = root_path.join("makky.metadata").to_owned();
 
+    let register = || {
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.clone(),
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: String::from("target").into(),
+        })
+    };
+    register().unwrap();
+    let err = register().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "register: write new entry: {}:2: new entry: target already registered: target",
+            metadata_path.display()
+        )
+    );
+    let metadata = read_to_string(&metadata_path).unwrap().replace(
+        "end-metadata\t1\n",
+        &format!(
+            "source\t{}\ntarget\ttarget\nend\nend-metadata\t2\n",
+            source_path.display()
+        ),
+    );
+    write(&metadata_path, metadata).unwrap();
+
     let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
         metadata_path: metadata_path.clone(),
-        target_root: root_path.clone(),
+        target_root: root_path,
     })
     .unwrap_err();
     assert!(err.source().is_some());
     assert_eq!(
         err.to_string(),
-        "link: read metadata: open config: No such file or directory (os error 2)"
+        format!(
+            "link: read metadata: parse entries:\n\t{}:5: entry: target duplicate: {} -> target, previous entry: {}:2",
+            metadata_path.display(),
+            source_path.display(),
+            metadata_path.display()
+        )
     );
     if let handler::Error::LinkReadMetadata(metadata_err) = err {
-        assert!(metadata_err.source().is_some());
+        assert!(metadata_err.source().is_none());
     } else {
         panic!("Unexpected error: {:?}", err);
     }
+}
 
-    let config_path = root_path.join("makky.metadata");
-    write(&config_path, "x").unwrap();
+#[test]
+fn target_escapes_root() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+
+    for target in ["../../etc/passwd", ".config/../../x", "a/../.."] {
+        let err = handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.clone(),
+            mode: None,
+            owner: None,
+            source: source_path.as_os_str().to_owned(),
+            target: target.into(),
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("register: create new entry: entry: target escapes the target root: {target}")
+        );
+    }
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: ".config/../target".into(),
+    })
+    .unwrap();
+
+    let metadata = read_to_string(&metadata_path).unwrap().replace(
+        "end-metadata\t1\n",
+        &format!(
+            "source\t{}\ntarget\t.config/../../x\nend\nend-metadata\t2\n",
+            source_path.display()
+        ),
+    );
+    write(&metadata_path, metadata).unwrap();
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.join("home"),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: target root is not a directory: {}",
+            root_path.join("home").display()
+        )
+    );
+    create_dir(root_path.join("home")).unwrap();
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.join("home"),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: parse entries:\n\t{}:5: entry: target escapes the target root: .config/../../x",
+            metadata_path.display()
+        )
+    );
+    assert!(!root_path.join("x").exists());
+    assert!(!root_path.join("home/target").exists());
+
+    let err = handler::validate(command::ArgsValidate {
+        metadata_path: metadata_path.clone(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "validate: parse entries:\n\t{}:5: entry: target escapes the target root: .config/../../x",
+            metadata_path.display()
+        )
+    );
 
+    let outside_path = root_path.join("outside/pwned");
+    write(
+        &metadata_path,
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\t{}\nend\nend-metadata\t1\n",
+            source_path.display(),
+            outside_path.display()
+        ),
+    )
+    .unwrap();
     let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.join("home"),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: parse entries:\n\t{}:2: new entry: target must be a relative path: {}",
+            metadata_path.display(),
+            outside_path.display()
+        )
+    );
+    assert!(!root_path.join("outside").exists());
+    let err = handler::status(command::ArgsStatus {
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.join("home"),
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("target must be a relative path"));
+
+    remove_file(&metadata_path).unwrap();
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: ".config/../target".into(),
+    })
+    .unwrap();
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.join("home"),
+    })
+    .unwrap();
+    assert_symlink_equals(&source_path, &root_path.join("home/target"));
+    assert!(!root_path.join("home/.config").exists());
+}
+
+#[test]
+fn switch_include() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let generation_path = root_path.join("generation");
+    create_dir(&generation_path).unwrap();
+    let state_path = root_path.join("state");
+    create_dir(&state_path).unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    let metadata_path = state_path.join("actual");
+    let new_metadata_path = generation_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    let included_source_path = root_path.join("included-source");
+    write(&included_source_path, "included-source").unwrap();
+    write(
+        &new_metadata_path,
+        format!(
+            "makky-metadata\t1\ninclude\tconfig\nsource\t{}\ntarget\ttarget\nend\nend-metadata\t2\n",
+            source_path.display()
+        ),
+    )
+    .unwrap();
+    write(
+        generation_path.join("config"),
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\tincluded\nend\nend-metadata\t1\n",
+            included_source_path.display()
+        ),
+    )
+    .unwrap();
+    let switch = || {
+        handler::switch(command::ArgsSwitch {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            new_metadata_path: new_metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+
+    switch().unwrap();
+    assert_symlink_equals(&source_path, &target_root.join("target"));
+    assert_symlink_equals(&included_source_path, &target_root.join("included"));
+    switch().unwrap();
+    assert_symlink_equals(&included_source_path, &target_root.join("included"));
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    assert!(!target_root.join("target").exists());
+    assert!(!target_root.join("included").exists());
+}
+
+#[test]
+fn link_include() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let shared_path = root_path.join("shared/makky.metadata");
+    create_dir(root_path.join("shared")).unwrap();
+    let link_args = || command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
         metadata_path: metadata_path.clone(),
         target_root: root_path.clone(),
+    };
+
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    let shared_source_path = root_path.join("shared-source");
+    write(&shared_source_path, "shared-source").unwrap();
+    write(
+        &metadata_path,
+        format!(
+            "makky-metadata\t1\ninclude\tshared/makky.metadata\nsource\t{}\ntarget\ttarget\nend\nend-metadata\t2\n",
+            source_path.display()
+        ),
+    )
+    .unwrap();
+    write(
+        &shared_path,
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\tshared-target\nend\nend-metadata\t1\n",
+            shared_source_path.display()
+        ),
+    )
+    .unwrap();
+    handler::link(link_args()).unwrap();
+    assert_symlink_equals(&source_path, &root_path.join("target"));
+    assert_symlink_equals(&shared_source_path, &root_path.join("shared-target"));
+
+    write(
+        &shared_path,
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\ttarget\nend\nend-metadata\t1\n",
+            shared_source_path.display()
+        ),
+    )
+    .unwrap();
+    let err = handler::link(link_args()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: parse entries:\n\t{}:3: entry: target duplicate: {} -> target, previous entry: {}:2",
+            metadata_path.display(),
+            source_path.display(),
+            shared_path.display()
+        )
+    );
+
+    write(
+        &shared_path,
+        "makky-metadata\t1\ninclude\t../makky.metadata\nend-metadata\t1\n",
+    )
+    .unwrap();
+    let err = handler::link(link_args()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: {}:2: include: cycle: {}",
+            shared_path.display(),
+            root_path.join("shared/../makky.metadata").display()
+        )
+    );
+
+    remove_file(&shared_path).unwrap();
+    let err = handler::link(link_args()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: {}:2: open config: No such file or directory (os error 2)",
+            metadata_path.display()
+        )
+    );
+}
+
+#[test]
+fn link_invalid_target_root() {
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: PathBuf::from("/tmp/makky"),
+        target_root: PathBuf::from("makky"),
     })
     .unwrap_err();
     assert!(err.source().is_some());
-    assert_eq!(err.to_string(), "link: read metadata: parse entry target: missing");
-    if let handler::Error::LinkReadMetadata(metadata_err) = err {
-        assert!(metadata_err.source().is_none());
-    } else {
-        panic!("Unexpected error: {:?}", err);
-    }
+    assert_eq!(
+        err.to_string(),
+        "link: read metadata: target root is not an absolute path: makky"
+    );
+
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: PathBuf::from("/tmp/makky"),
+        target_root: PathBuf::from("/tmp/makky"),
+    })
+    .unwrap_err();
+    assert!(err.source().is_some());
+    assert_eq!(
+        err.to_string(),
+        "link: read metadata: target root is not a directory: /tmp/makky"
+    );
+}
+
+#[test]
+fn link_invalid_config() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata").to_owned();
+
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap_err();
+    assert!(err.source().is_some());
+    assert_eq!(
+        err.to_string(),
+        "link: read metadata: open config: No such file or directory (os error 2)"
+    );
+    if let handler::Error::LinkReadMetadata(metadata_err) = err {
+        assert!(metadata_err.source().is_some());
+    } else {
+        panic!("Unexpected error: {:?}", err);
+    }
+
+    let config_path = root_path.join("makky.metadata");
+    write(&config_path, "x").unwrap();
+
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: root_path.clone(),
+    })
+    .unwrap_err();
+    assert!(err.source().is_some());
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: {}:1: parse entry target: missing",
+            config_path.display()
+        )
+    );
+    if let handler::Error::LinkReadMetadata(metadata_err) = err {
+        assert!(metadata_err.source().is_some());
+    } else {
+        panic!("Unexpected error: {:?}", err);
+    }
+
+    for (data, expected) in [
+        ("makky-metadata\t2\n", "1: unsupported version: 2"),
+        ("makky-metadata\t1\nsource\t/x\n", "2: parse entry: unterminated"),
+        (
+            "makky-metadata\t1\n\nsource\t/x\nend\n",
+            "3: parse entry target: missing",
+        ),
+        (
+            "makky-metadata\t1\nsource\t/x\nsource\t/y\nend\n",
+            "3: parse entry attribute: duplicate: source",
+        ),
+        (
+            "makky-metadata\t1\n# comment\n\nsource\n",
+            "4: parse entry attribute: invalid: source",
+        ),
+        ("/x\n\n# comment\n", "3: parse entry target: missing"),
+    ] {
+        write(&config_path, data).unwrap();
+        let err = handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: root_path.clone(),
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("link: read metadata: {}:{expected}", config_path.display())
+        );
+    }
+}
+
+#[test]
+fn link_target_overlap() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_dir_path = root_path.join("nvim");
+    create_dir(&source_dir_path).unwrap();
+    write(source_dir_path.join("init.lua"), "init").unwrap();
+    let source_file_path = root_path.join("init.lua");
+    write(&source_file_path, "override").unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+
+    for (source, target) in [
+        (&source_dir_path, ".config/nvim"),
+        (&source_file_path, ".config/nvim/init.lua"),
+        (&source_file_path, "./.config/nvim"),
+    ] {
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Symlink,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.clone(),
+            mode: None,
+            owner: None,
+            source: source.as_os_str().to_owned(),
+            target: target.into(),
+        })
+        .unwrap();
+    }
+
+    let err = handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: parse entries:\n\
+             \t{path}:5: entry: target overlaps with {root}/.config/nvim: {root}/.config/nvim/init.lua, previous entry: {path}:2\n\
+             \t{path}:8: entry: target is equivalent to a previous one: {root}/.config/nvim, previous entry: {path}:2",
+            path = metadata_path.display(),
+            root = target_root.display()
+        )
+    );
+    assert!(!target_root.join(".config").exists());
+
+    let err = handler::status(command::ArgsStatus {
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap_err();
+    assert!(err.to_string().starts_with("status: read metadata: parse entries:"));
+}
+
+#[test]
+fn sha256_digest() {
+    for (data, expected) in [
+        (
+            Vec::new(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abc".to_vec(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".to_vec(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            vec![b'a'; 1_000_000],
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+        ),
+    ] {
+        assert_eq!(sha256::digest(&data), expected);
+    }
+}
+
+#[test]
+fn link_copy() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_file_path = root_path.join("source-file");
+    write(&source_file_path, "file").unwrap();
+    set_permissions(&source_file_path, Permissions::from_mode(0o640)).unwrap();
+    let source_dir_path = root_path.join("source-dir");
+    create_dir_all(source_dir_path.join("nested")).unwrap();
+    write(source_dir_path.join("nested/file"), "nested").unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    let target_file_path = target_root.join("file");
+    let target_nested_path = target_root.join("dir/nested/file");
+
+    for (source, target) in [(&source_file_path, "file"), (&source_dir_path, "dir")] {
+        handler::register(command::ArgsRegister {
+            deploy: symlink::Deploy::Copy,
+            dir_mode: None,
+            group: None,
+            metadata_path: metadata_path.clone(),
+            mode: None,
+            owner: None,
+            source: source.as_os_str().to_owned(),
+            target: target.into(),
+        })
+        .unwrap();
+    }
+    assert!(read_to_string(&metadata_path).unwrap().contains("deploy\tcopy\nend\n"));
+
+    let link = || {
+        handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let status = || {
+        handler::status(command::ArgsStatus {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    link().unwrap();
+    let metadata = symlink_metadata(&target_file_path).unwrap();
+    assert!(metadata.is_file());
+    assert_eq!(metadata.mode() & 0o777, 0o640);
+    assert_eq!(read_to_string(&target_file_path).unwrap(), "file");
+    assert!(symlink_metadata(&target_nested_path).unwrap().is_file());
+    assert_eq!(read_to_string(&target_nested_path).unwrap(), "nested");
+    status().unwrap();
+    link().unwrap();
+
+    write(&source_file_path, "file-updated").unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    link().unwrap();
+    assert_eq!(read_to_string(&target_file_path).unwrap(), "file-updated");
+    status().unwrap();
+
+    write(&target_nested_path, "edited").unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    write(source_dir_path.join("nested/file"), "nested-updated").unwrap();
+    let err = link().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: create {} -> {}: target was modified after deployment: {}",
+            source_dir_path.display(),
+            target_root.join("dir").display(),
+            target_nested_path.display()
+        )
+    );
+    assert_eq!(read_to_string(&target_nested_path).unwrap(), "edited");
+    let err = handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: remove {} -> {}: target was modified after deployment: {}",
+            source_dir_path.display(),
+            target_root.join("dir").display(),
+            target_nested_path.display()
+        )
+    );
+
+    write(&target_nested_path, "nested").unwrap();
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    assert!(!target_file_path.exists());
+    assert!(!target_root.join("dir").exists());
+    assert!(!root_path.join("makky.metadata.manifest").exists());
+}
+
+#[test]
+fn unified_diff() {
+    assert_eq!(unified::diff(b"a\n", b"a\n", "old", "new"), "");
+    assert_eq!(
+        unified::diff(b"a\0", b"b\0", "old", "new"),
+        "Binary files old and new differ\n"
+    );
+    assert_eq!(
+        unified::diff(
+            b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n",
+            b"a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk",
+            "old",
+            "new"
+        ),
+        "--- old\n\
+         +++ new\n\
+         @@ -1,5 +1,5 @@\n \
+         a\n\
+         -b\n\
+         +B\n \
+         c\n \
+         d\n \
+         e\n\
+         @@ -8,3 +8,4 @@\n \
+         h\n \
+         i\n \
+         j\n\
+         +k\n\
+         \\ No newline at end of file\n"
+    );
+    assert_eq!(
+        unified::diff(b"", b"x\n", "old", "new"),
+        "--- old\n+++ new\n@@ -0,0 +1 @@\n+x\n"
+    );
+
+    let old: String = (0..5000).map(|idx| format!("{}\n", idx % 7)).collect();
+    let new: String = (0..5000).map(|idx| format!("{}\n", idx % 5)).collect();
+    let diff = unified::diff(old.as_bytes(), new.as_bytes(), "old", "new");
+    let deleted = diff.lines().filter(|line| line.starts_with('-')).count() - 1;
+    let inserted = diff.lines().filter(|line| line.starts_with('+')).count() - 1;
+    assert_eq!(deleted, inserted);
+    assert!(deleted < 5000);
+}
+
+#[test]
+fn diff_copy() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "source\n").unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Copy,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: "copy".into(),
+    })
+    .unwrap();
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Symlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: "link".into(),
+    })
+    .unwrap();
+    let diff = |target: Option<&str>| {
+        handler::diff(command::ArgsDiff {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+            target: target.map(Into::into),
+        })
+    };
+
+    let err = diff(None).unwrap_err();
+    assert_eq!(err.to_string(), "diff: files drifted: 1");
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Fail,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    diff(None).unwrap();
+    diff(Some("copy")).unwrap();
+
+    write(target_root.join("copy"), "edited\n").unwrap();
+    let err = diff(Some("./copy")).unwrap_err();
+    assert!(err.source().is_none());
+    assert_eq!(err.to_string(), "diff: files drifted: 1");
+    let err = diff(Some("link")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("diff: target is not copied: {}", target_root.join("link").display())
+    );
+
+    let template_path = root_path.join("template");
+    write(&template_path, "host=@HOSTNAME@\n").unwrap();
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Template,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: template_path.as_os_str().to_owned(),
+        target: "template".into(),
+    })
+    .unwrap();
+    handler::link(command::ArgsLink {
+        conflict: symlink::Conflict::Overwrite,
+        cross_device: symlink::CrossDevice::Fail,
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    diff(Some("template")).unwrap();
+    write(target_root.join("template"), "edited\n").unwrap();
+    let err = diff(Some("template")).unwrap_err();
+    assert_eq!(err.to_string(), "diff: files drifted: 1");
+    let manifest = manifest::Manifest::read(&metadata_path).unwrap();
+    let copies = symlink::drift(
+        &template_path,
+        target_root.join("template"),
+        symlink::Deploy::Template,
+        &manifest,
+    )
+    .unwrap();
+    assert_eq!(copies.len(), 1);
+    assert_eq!(copies[0].drift, symlink::Drift::Modified);
+    let variables = template::variables();
+    assert_eq!(
+        copies[0].expected(&variables).unwrap(),
+        format!("host={}\n", variables["HOSTNAME"]).into_bytes()
+    );
+}
+
+#[test]
+fn link_hardlink() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    let target_path = target_root.join(".config/file");
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Hardlink,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: ".config/file".into(),
+    })
+    .unwrap();
+    let link = |cross_device| {
+        handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let status = || {
+        handler::status(command::ArgsStatus {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let unlink = || {
+        handler::unlink(command::ArgsUnlink {
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+
+    link(symlink::CrossDevice::Fail).unwrap();
+    let target_metadata = symlink_metadata(&target_path).unwrap();
+    assert!(target_metadata.is_file());
+    assert_eq!(target_metadata.ino(), symlink_metadata(&source_path).unwrap().ino());
+    status().unwrap();
+    link(symlink::CrossDevice::Fail).unwrap();
+
+    let new_source_path = root_path.join("source-new");
+    write(&new_source_path, "source-new").unwrap();
+    std::fs::rename(&new_source_path, &source_path).unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    link(symlink::CrossDevice::Fail).unwrap();
+    assert_eq!(
+        symlink_metadata(&target_path).unwrap().ino(),
+        symlink_metadata(&source_path).unwrap().ino()
+    );
+    assert_eq!(read_to_string(&target_path).unwrap(), "source-new");
+    status().unwrap();
+
+    remove_file(&target_path).unwrap();
+    write(&target_path, "replaced").unwrap();
+    let err = unlink().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: remove {} -> {}: target was modified after deployment: {}",
+            source_path.display(),
+            target_path.display(),
+            target_path.display()
+        )
+    );
+    remove_file(&target_path).unwrap();
+    std::fs::hard_link(&source_path, &target_path).unwrap();
+    unlink().unwrap();
+    assert!(source_path.exists());
+    assert!(!target_root.join(".config").exists());
+
+    let Ok(other_device) = tempfile::tempdir_in("/dev/shm") else {
+        return;
+    };
+    if other_device.path().metadata().unwrap().dev() == root_path.metadata().unwrap().dev() {
+        return;
+    }
+    let target_root = other_device.path().to_owned();
+    let target_path = target_root.join(".config/file");
+    let link = |cross_device| {
+        handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let err = link(symlink::CrossDevice::Fail).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: create {} -> {}: source and target are on different devices: {} -> {}",
+            source_path.display(),
+            target_path.display(),
+            source_path.display(),
+            target_path.display()
+        )
+    );
+    assert!(!target_path.exists());
+    link(symlink::CrossDevice::Copy).unwrap();
+    assert!(symlink_metadata(&target_path).unwrap().is_file());
+    assert_eq!(read_to_string(&target_path).unwrap(), "source-new");
+    handler::status(command::ArgsStatus {
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    link(symlink::CrossDevice::Copy).unwrap();
+
+    let diff = || {
+        handler::diff(command::ArgsDiff {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+            target: None,
+        })
+    };
+    diff().unwrap();
+    write(&target_path, "edited").unwrap();
+    assert_eq!(diff().unwrap_err().to_string(), "diff: files drifted: 1");
+}
+
+#[test]
+fn template_render() {
+    let variables = template::Variables::from([
+        (String::from("HOME"), String::from("/home/user")),
+        (String::from("USER"), String::from("user")),
+    ]);
+    assert_eq!(
+        template::render(b"@USER@ lives in @HOME@", &variables).unwrap(),
+        b"user lives in /home/user"
+    );
+    assert_eq!(
+        template::render(b"user@example.com @@HOME@ @ @1@ @@", &variables).unwrap(),
+        b"user@example.com @HOME@ @ @1@ @"
+    );
+    assert_eq!(template::render(b"", &variables).unwrap(), b"");
+    assert_eq!(
+        template::render(b"@EDITOR@ @USER@ @SHELL@ @EDITOR@", &variables).unwrap_err(),
+        vec![String::from("EDITOR"), String::from("SHELL")]
+    );
+
+    let variables = template::variables();
+    if let Some(home) = variables.get("HOME") {
+        assert_eq!(
+            variables["XDG_CONFIG_HOME"],
+            std::env::var("XDG_CONFIG_HOME").unwrap_or(format!("{home}/.config"))
+        );
+    }
+    assert!(!variables.contains_key("PATH"));
+}
+
+#[test]
+fn link_template() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let source_path = root_path.join("source");
+    write(&source_path, "host=@HOSTNAME@\n").unwrap();
+    set_permissions(&source_path, Permissions::from_mode(0o640)).unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    let target_path = target_root.join(".config/file");
+    let hostname = template::variables().remove("HOSTNAME").unwrap();
+    handler::register(command::ArgsRegister {
+        deploy: symlink::Deploy::Template,
+        dir_mode: None,
+        group: None,
+        metadata_path: metadata_path.clone(),
+        mode: None,
+        owner: None,
+        source: source_path.as_os_str().to_owned(),
+        target: ".config/file".into(),
+    })
+    .unwrap();
+    let link = || {
+        handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let status = || {
+        handler::status(command::ArgsStatus {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+
+    link().unwrap();
+    let target_metadata = symlink_metadata(&target_path).unwrap();
+    assert!(target_metadata.is_file());
+    assert_eq!(target_metadata.mode() & 0o777, 0o640);
+    assert_eq!(read_to_string(&target_path).unwrap(), format!("host={hostname}\n"));
+    status().unwrap();
+    link().unwrap();
+
+    write(&source_path, "host=@HOSTNAME@\nmail=user@@example.com\n").unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    let mut plan = symlink::Plan::new(
+        symlink::Conflict::Fail,
+        symlink::CrossDevice::Fail,
+        &manifest::Manifest::read(&metadata_path).unwrap(),
+    );
+    plan.create(
+        &source_path,
+        &target_path,
+        symlink::Deploy::Template,
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(matches!(plan.operations(), [symlink::Operation::RenderTemplate { .. }]));
+    link().unwrap();
+    assert_eq!(
+        read_to_string(&target_path).unwrap(),
+        format!("host={hostname}\nmail=user@example.com\n")
+    );
+    status().unwrap();
+
+    write(&source_path, "@MAKKY_UNDEFINED@ @HOSTNAME@ @PATH@\n").unwrap();
+    let err = link().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: create {} -> {}: template variables are not defined: {}: MAKKY_UNDEFINED, PATH",
+            source_path.display(),
+            target_path.display(),
+            source_path.display()
+        )
+    );
+    assert_eq!(
+        read_to_string(&target_path).unwrap(),
+        format!("host={hostname}\nmail=user@example.com\n")
+    );
+
+    write(&source_path, "host=@HOSTNAME@\n").unwrap();
+    write(&target_path, "edited").unwrap();
+    let err = link().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: create {} -> {}: target was modified after deployment: {}",
+            source_path.display(),
+            target_path.display(),
+            target_path.display()
+        )
+    );
+}
+
+#[test]
+fn link_access_not_root() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    let copy_target_path = root_path.join(".ssh/config");
+    let symlink_target_path = root_path.join("link");
+    let access = symlink::Access {
+        mode: Some(0o600),
+        dir_mode: Some(0o700),
+        owner: Some(1),
+        group: Some(2),
+    }
+    .effective(1000);
+    assert_eq!(
+        access,
+        symlink::Access {
+            mode: Some(0o600),
+            dir_mode: Some(0o700),
+            owner: None,
+            group: None,
+        }
+    );
+
+    let mut plan = symlink::Plan::new(symlink::Conflict::Fail, symlink::CrossDevice::Fail, &Default::default());
+    plan.create(&source_path, &copy_target_path, symlink::Deploy::Copy, &access)
+        .unwrap();
+    plan.create(&source_path, &symlink_target_path, symlink::Deploy::Symlink, &access)
+        .unwrap();
+    plan.finish();
+    assert!(matches!(
+        plan.operations(),
+        [
+            symlink::Operation::CreateDirectory {
+                attributes: symlink::Attributes {
+                    mode: Some(0o700),
+                    owner: None,
+                    group: None,
+                },
+                ..
+            },
+            symlink::Operation::CopyFile {
+                attributes: symlink::Attributes {
+                    mode: Some(0o600),
+                    owner: None,
+                    group: None,
+                },
+                ..
+            },
+            symlink::Operation::CreateSymlink { .. },
+        ]
+    ));
+    plan.apply(|_| {}).unwrap();
+    assert_eq!(
+        symlink::status(
+            &source_path,
+            &symlink_target_path,
+            symlink::Deploy::Symlink,
+            &access,
+            &Default::default(),
+        )
+        .unwrap(),
+        symlink::Status::Linked
+    );
+}
+
+#[test]
+fn link_access() {
+    let root = tempdir().unwrap();
+    let root_path = root.path().to_owned();
+    let metadata_path = root_path.join("makky.metadata");
+    let json_path = root_path.join("makky.json");
+    let source_path = root_path.join("source");
+    write(&source_path, "source").unwrap();
+    let target_root = root_path.join("home");
+    create_dir(&target_root).unwrap();
+    let directory_path = target_root.join(".ssh");
+    let target_path = directory_path.join("config");
+    let is_root = symlink_metadata("/proc/self").is_ok_and(|metadata| metadata.uid() == 0);
+    let ownership = if is_root { r#", "owner": "1", "group": "2""# } else { "" };
+    write(
+        &json_path,
+        format!(
+            r#"[{{"source": "{}", "target": ".ssh/config", "deploy": "copy", "mode": "600", "dir_mode": "0700"{ownership}}}]"#,
+            source_path.display()
+        ),
+    )
+    .unwrap();
+    handler::import(command::ArgsImport {
+        metadata_path: metadata_path.clone(),
+        json_path: json_path.clone(),
+    })
+    .unwrap();
+    let link = || {
+        handler::link(command::ArgsLink {
+            conflict: symlink::Conflict::Fail,
+            cross_device: symlink::CrossDevice::Fail,
+            dry_run: false,
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+    let status = || {
+        handler::status(command::ArgsStatus {
+            metadata_path: metadata_path.clone(),
+            target_root: target_root.clone(),
+        })
+    };
+
+    let mut plan = symlink::Plan::new(symlink::Conflict::Fail, symlink::CrossDevice::Fail, &Default::default());
+    let access = symlink::Access {
+        mode: Some(0o600),
+        dir_mode: Some(0o700),
+        owner: None,
+        group: None,
+    };
+    plan.create(&source_path, &target_path, symlink::Deploy::Copy, &access)
+        .unwrap();
+    plan.finish();
+    assert!(matches!(
+        plan.operations(),
+        [
+            symlink::Operation::CreateDirectory {
+                attributes: symlink::Attributes { mode: Some(0o700), .. },
+                ..
+            },
+            symlink::Operation::CopyFile {
+                attributes: symlink::Attributes { mode: Some(0o600), .. },
+                ..
+            },
+        ]
+    ));
+
+    link().unwrap();
+    let target_metadata = symlink_metadata(&target_path).unwrap();
+    assert_eq!(target_metadata.mode() & 0o7777, 0o600);
+    assert_eq!(symlink_metadata(&directory_path).unwrap().mode() & 0o7777, 0o700);
+    if is_root {
+        assert_eq!((target_metadata.uid(), target_metadata.gid()), (1, 2));
+        let directory_metadata = symlink_metadata(&directory_path).unwrap();
+        assert_eq!((directory_metadata.uid(), directory_metadata.gid()), (1, 2));
+    }
+    status().unwrap();
+    link().unwrap();
+
+    set_permissions(&target_path, Permissions::from_mode(0o644)).unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    link().unwrap();
+    assert_eq!(symlink_metadata(&target_path).unwrap().mode() & 0o7777, 0o600);
+    status().unwrap();
+
+    set_permissions(&directory_path, Permissions::from_mode(0o755)).unwrap();
+    let err = status().unwrap_err();
+    assert_eq!(err.to_string(), "status: entries drifted: 1");
+    link().unwrap();
+    assert_eq!(symlink_metadata(&directory_path).unwrap().mode() & 0o7777, 0o700);
+    status().unwrap();
+
+    if is_root {
+        std::os::unix::fs::lchown(&target_path, Some(0), Some(0)).unwrap();
+        let err = status().unwrap_err();
+        assert_eq!(err.to_string(), "status: entries drifted: 1");
+        link().unwrap();
+        let target_metadata = symlink_metadata(&target_path).unwrap();
+        assert_eq!((target_metadata.uid(), target_metadata.gid()), (1, 2));
+        status().unwrap();
+    }
+
+    handler::unlink(command::ArgsUnlink {
+        dry_run: false,
+        metadata_path: metadata_path.clone(),
+        target_root: target_root.clone(),
+    })
+    .unwrap();
+    assert!(!directory_path.exists());
+
+    write(
+        &metadata_path,
+        format!(
+            "makky-metadata\t1\nsource\t{}\ntarget\tfile\nowner\tmakky-unknown-user\nend\nend-metadata\t1\n",
+            source_path.display()
+        ),
+    )
+    .unwrap();
+    let err = link().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "link: read metadata: parse entries:\n\t{}:2: entry: unknown owner: makky-unknown-user",
+            metadata_path.display()
+        )
+    );
 }
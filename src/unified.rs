@@ -0,0 +1,190 @@
+use std::fmt::Write;
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy)]
+enum Edit {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+pub fn diff(old: &[u8], new: &[u8], old_name: &str, new_name: &str) -> String {
+    let mut out = String::new();
+    if old == new {
+        return out;
+    }
+    if old.contains(&0) || new.contains(&0) {
+        let _ = writeln!(out, "Binary files {old_name} and {new_name} differ");
+        return out;
+    }
+    let old_lines: Vec<&[u8]> = old.split_inclusive(|x| *x == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new.split_inclusive(|x| *x == b'\n').collect();
+    let edits = edits(&old_lines, &new_lines);
+
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in &edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal(_) => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Edit::Delete(_) => old_pos += 1,
+            Edit::Insert(_) => new_pos += 1,
+        }
+    }
+
+    let _ = writeln!(out, "--- {old_name}");
+    let _ = writeln!(out, "+++ {new_name}");
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|idx| !matches!(edits[*idx], Edit::Equal(_)))
+        .collect();
+    let mut idx = 0;
+    while idx < changes.len() {
+        let first = changes[idx];
+        let mut last = first;
+        idx += 1;
+        while idx < changes.len() && changes[idx] - last <= 2 * CONTEXT {
+            last = changes[idx];
+            idx += 1;
+        }
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(edits.len());
+        let hunk = &edits[start..end];
+        let old_count = hunk.iter().filter(|edit| !matches!(edit, Edit::Insert(_))).count();
+        let new_count = hunk.iter().filter(|edit| !matches!(edit, Edit::Delete(_))).count();
+        let (old_start, new_start) = positions[start];
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        );
+        for edit in hunk {
+            let (prefix, line) = match *edit {
+                Edit::Equal(idx) => (' ', old_lines[idx]),
+                Edit::Delete(idx) => ('-', old_lines[idx]),
+                Edit::Insert(idx) => ('+', new_lines[idx]),
+            };
+            out.push(prefix);
+            out.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn edits(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    let mut result = Vec::with_capacity(old.len() + new.len());
+    let size = (old.len() + new.len()).div_ceil(2) + 1;
+    let mut forward = vec![0; 2 * size + 1];
+    let mut backward = vec![0; 2 * size + 1];
+    conquer(old, new, (0, 0), &mut forward, &mut backward, &mut result);
+    for changes in result.split_mut(|edit| matches!(edit, Edit::Equal(_))) {
+        changes.sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+    }
+    result
+}
+
+fn conquer(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    (old_start, new_start): (usize, usize),
+    forward: &mut [usize],
+    backward: &mut [usize],
+    result: &mut Vec<Edit>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    result.extend((0..prefix).map(|idx| Edit::Equal(old_start + idx)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let (old_start, new_start) = (old_start + prefix, new_start + prefix);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    if old.is_empty() || new.is_empty() {
+        result.extend((0..old.len()).map(|idx| Edit::Delete(old_start + idx)));
+        result.extend((0..new.len()).map(|idx| Edit::Insert(new_start + idx)));
+    } else if let Some((x, y)) = middle_snake(old, new, forward, backward) {
+        conquer(&old[..x], &new[..y], (old_start, new_start), forward, backward, result);
+        conquer(
+            &old[x..],
+            &new[y..],
+            (old_start + x, new_start + y),
+            forward,
+            backward,
+            result,
+        );
+    } else {
+        result.extend((0..old.len()).map(|idx| Edit::Delete(old_start + idx)));
+        result.extend((0..new.len()).map(|idx| Edit::Insert(new_start + idx)));
+    }
+    result.extend((0..suffix).map(|idx| Edit::Equal(old_start + old.len() + idx)));
+}
+
+fn middle_snake(old: &[&[u8]], new: &[&[u8]], forward: &mut [usize], backward: &mut [usize]) -> Option<(usize, usize)> {
+    let n = old.len();
+    let m = new.len();
+    let delta = n as isize - m as isize;
+    let odd = delta % 2 != 0;
+    let max = (n + m).div_ceil(2) + 1;
+    let index = |k: isize| (k + max as isize) as usize;
+    forward[index(1)] = 0;
+    backward[index(1)] = 0;
+    for d in 0..max as isize {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let y = (x as isize - k) as usize;
+            let start = (x, y);
+            if x < n && y < m {
+                x += old[x..].iter().zip(&new[y..]).take_while(|(a, b)| a == b).count();
+            }
+            forward[index(k)] = x;
+            if odd && (k - delta).abs() < d && x + backward[index(delta - k)] >= n {
+                return Some(start);
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let common = old[..n - x]
+                    .iter()
+                    .rev()
+                    .zip(new[..m - y].iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                x += common;
+                y += common;
+            }
+            backward[index(k)] = x;
+            if !odd && (k - delta).abs() <= d && x + forward[index(delta - k)] >= n {
+                return Some((n - x, m - y));
+            }
+        }
+    }
+    None
+}